@@ -0,0 +1,27 @@
+//! Form body of `POST /validate`, parsed as `Server` does, and the `UserInfo` fields of GeeTest requests.
+
+#![no_main]
+
+use geetest::{
+    models::{ClientValidateRequest, UserInfo},
+    protocol,
+};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(request) = protocol::parse_validate_body(data) {
+        // Whatever is accepted must survive a round trip unchanged.
+        let encoded = serde_qs::to_string(&request).expect("parsed request serializes");
+        let decoded: ClientValidateRequest = serde_qs::from_str(&encoded).expect("serialized request parses");
+        assert_eq!(decoded.challenge, request.challenge);
+        assert_eq!(decoded.validate, request.validate);
+        assert_eq!(decoded.seccode, request.seccode);
+        assert_eq!(decoded.session_binding, request.session_binding);
+    }
+
+    if let Ok(user_info) = serde_qs::from_bytes::<UserInfo>(data) {
+        let encoded = serde_qs::to_string(&user_info).expect("parsed user info serializes");
+        let decoded: UserInfo = serde_qs::from_str(&encoded).expect("serialized user info parses");
+        assert_eq!(decoded, user_info);
+    }
+});
@@ -0,0 +1,26 @@
+//! GeeTest API responses, decoded from JSON as `Client` does.
+
+#![no_main]
+
+use geetest::models::{
+    ClientRegisterResponse, ClientValidateResponse, ServerRegisterResponse, ServerValidateResponse, StatusResponse,
+};
+use libfuzzer_sys::fuzz_target;
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Accepted values re-encode to JSON that decodes to the same value.
+fn check<T: Serialize + DeserializeOwned>(data: &[u8]) {
+    if let Ok(value) = serde_json::from_slice::<T>(data) {
+        let encoded = serde_json::to_value(&value).expect("decoded value serializes");
+        let decoded: T = serde_json::from_value(encoded.clone()).expect("serialized value decodes");
+        assert_eq!(serde_json::to_value(&decoded).unwrap(), encoded);
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    check::<StatusResponse>(data);
+    check::<ServerRegisterResponse>(data);
+    check::<ServerValidateResponse>(data);
+    check::<ClientRegisterResponse>(data);
+    check::<ClientValidateResponse>(data);
+});
@@ -0,0 +1,93 @@
+//! Arbitrary requests routed by `Server::call`, backed by a fake GeeTest backend.
+//!
+//! Input is an HTTP-like request: `METHOD /path?query` line, `name: value` header lines, an empty line and the
+//! body. A leading `!` selects the server with session binding and pass tokens.
+
+#![no_main]
+
+use geetest::{
+    assets::Assets,
+    backend::FakeBackend,
+    server::{Handler, Server},
+    session::SessionBinder,
+    token::PassTokenIssuer,
+    DigestMod,
+};
+use hyper::{service::Service, Body, Request};
+use libfuzzer_sys::fuzz_target;
+use std::time::Duration;
+
+struct Servers {
+    runtime: tokio::runtime::Runtime,
+    plain: Server,
+    bound: Server,
+}
+
+thread_local! {
+    static SERVERS: Servers = {
+        let backend = FakeBackend::new("fuzz-captcha-id", DigestMod::Md5);
+        backend.reject("rejected");
+
+        let plain = Server::from_handler(Handler::builder(backend.clone(), "fuzz-secret").build().unwrap())
+            .with_remote_addr(([203, 0, 113, 7], 40000).into())
+            .assets(Assets::default());
+        let bound = Server::from_handler(
+            Handler::builder(backend, "fuzz-secret")
+                .failback_on_error(true)
+                .session_binder(SessionBinder::new("fuzz-session-key"))
+                .pass_token_issuer(PassTokenIssuer::new("fuzz-token-key", "fuzz", Duration::from_secs(60)))
+                .build()
+                .unwrap(),
+        )
+        .trust_forwarded_for(true)
+        .secure_cookie(true);
+
+        Servers {
+            runtime: tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap(),
+            plain,
+            bound,
+        }
+    };
+}
+
+fn parse_request(data: &[u8]) -> Option<Request<Body>> {
+    let head_len = data
+        .windows(2)
+        .position(|window| window == b"\n\n")
+        .unwrap_or(data.len());
+    let head = std::str::from_utf8(&data[..head_len]).ok()?;
+    let body = data.get(head_len + 2..).unwrap_or_default().to_vec();
+
+    let mut lines = head.lines();
+    let (method, uri) = lines.next()?.split_once(' ')?;
+    let mut request = Request::builder().method(method).uri(uri);
+    for line in lines {
+        if let Some((name, value)) = line.split_once(':') {
+            request = request.header(name.trim(), value.trim());
+        }
+    }
+    request.body(Body::from(body)).ok()
+}
+
+fuzz_target!(|data: &[u8]| {
+    let (bound, data) = match data.strip_prefix(b"!") {
+        Some(data) => (true, data),
+        None => (false, data),
+    };
+    let request = match parse_request(data) {
+        Some(request) => request,
+        None => return,
+    };
+
+    SERVERS.with(|servers| {
+        let mut server = if bound {
+            servers.bound.clone()
+        } else {
+            servers.plain.clone()
+        };
+        let response = servers.runtime.block_on(server.call(request));
+        // Every request gets an HTTP response, errors are turned into error replies.
+        let response = response.expect("server replies to every request");
+        assert!(!response.status().is_informational());
+    });
+});
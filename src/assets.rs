@@ -0,0 +1,186 @@
+//! Embedded official `gt.js`, served by [`Server`] with `Cache-Control` and `ETag` headers along with a minimal
+//! demo page. [`Assets::snippet`] renders HTML wiring `initGeetest` to the register and validate paths, the
+//! validate reply is dispatched as a `geetest:validate` DOM event.
+//!
+//! [`Server`]: crate::server::Server
+
+use crate::digest::fnv1a;
+use serde_derive::Deserialize;
+
+/// Official GeeTest front-end library providing `initGeetest()`.
+pub static GT_JS: &[u8] = include_bytes!("../static/gt.js");
+
+const GT_JS_HASH: u64 = fnv1a(GT_JS);
+
+/// Strong ETag of the embedded `gt.js`.
+pub fn gt_js_etag() -> String {
+    format!("\"{:016x}\"", GT_JS_HASH)
+}
+
+/// Front-end assets served by [`Server`](crate::server::Server) and the captcha widget wiring.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct Assets {
+    pub gt_js_path: String,
+    /// Demo page is not served if `None`.
+    pub demo_path: Option<String>,
+    pub demo_title: String,
+    pub register_path: String,
+    pub validate_path: String,
+    /// GeeTest widget product: `float`, `popup` or `bind`.
+    pub product: String,
+    pub lang: String,
+}
+
+impl Default for Assets {
+    fn default() -> Self {
+        Self {
+            gt_js_path: "/static/gt.js".to_owned(),
+            demo_path: Some("/".to_owned()),
+            demo_title: "GeeTest demo".to_owned(),
+            register_path: "/register".to_owned(),
+            validate_path: "/validate".to_owned(),
+            product: "float".to_owned(),
+            lang: "en".to_owned(),
+        }
+    }
+}
+
+impl Assets {
+    /// HTML loading `gt.js` and a script that appends the widget to the `container` element, gets a challenge
+    /// from the register path and posts the result to the validate path once the captcha is solved.
+    ///
+    /// The validate reply is dispatched as a `geetest:validate` event on `document`.
+    pub fn snippet(&self, container: &str) -> String {
+        format!(
+            r#"<script src="{gt_js}"></script>
+<script>
+(function () {{
+    fetch({register} + "?t=" + Date.now(), {{ credentials: "same-origin" }})
+        .then(function (response) {{ return response.json(); }})
+        .then(function (data) {{
+            initGeetest({{
+                gt: data.gt,
+                challenge: data.challenge,
+                new_captcha: data.new_captcha,
+                offline: !data.success,
+                product: {product},
+                width: "100%",
+                lang: {lang}
+            }}, function (captchaObj) {{
+                captchaObj.appendTo({container});
+                captchaObj.onSuccess(function () {{
+                    var body = new URLSearchParams(captchaObj.getValidate());
+                    if (data.session_binding) {{
+                        body.append("geetest_session_binding", data.session_binding);
+                    }}
+                    fetch({validate}, {{ method: "POST", credentials: "same-origin", body: body }})
+                        .then(function (response) {{ return response.json(); }})
+                        .then(function (reply) {{
+                            document.dispatchEvent(new CustomEvent("geetest:validate", {{ detail: reply }}));
+                        }});
+                }});
+            }});
+        }});
+}})();
+</script>"#,
+            gt_js = html_escape(&self.gt_js_path),
+            register = js_string(&self.register_path),
+            validate = js_string(&self.validate_path),
+            product = js_string(&self.product),
+            lang = js_string(&self.lang),
+            container = js_string(container),
+        )
+    }
+
+    pub fn demo_page(&self) -> String {
+        format!(
+            r#"<!DOCTYPE html>
+<html>
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1">
+    <title>{title}</title>
+    <style>
+        body {{ margin: 50px 0; text-align: center; font-family: Arial, sans-serif; }}
+        #captcha {{ width: 300px; display: inline-block; }}
+    </style>
+</head>
+<body>
+<h1>{title}</h1>
+<div id="captcha"></div>
+<p id="result"></p>
+{snippet}
+<script>
+    document.addEventListener("geetest:validate", function (event) {{
+        document.getElementById("result").textContent = event.detail.result === "success"
+            ? "Captcha passed"
+            : "Captcha failed: " + event.detail.msg;
+    }});
+</script>
+</body>
+</html>
+"#,
+            title = html_escape(&self.demo_title),
+            snippet = self.snippet("#captcha"),
+        )
+    }
+}
+
+fn html_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+/// JavaScript string literal safe to embed in a `<script>` element.
+fn js_string(value: &str) -> String {
+    serde_json::to_string(value)
+        .expect("Strings are always serializable")
+        .replace('<', "\\u003c")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snippet_escapes_paths() {
+        let assets = Assets {
+            gt_js_path: "/gt.js\"><script>alert(1)</script>".to_owned(),
+            register_path: "/register\"</script><script>alert(2)//".to_owned(),
+            validate_path: "/validate'\\".to_owned(),
+            ..Assets::default()
+        };
+        let snippet = assets.snippet("#captcha");
+
+        assert!(snippet.contains(r#"<script src="/gt.js&quot;&gt;&lt;script&gt;alert(1)&lt;/script&gt;"></script>"#));
+        assert!(snippet.contains(r#"fetch("/register\"\u003c/script>\u003cscript>alert(2)//" + "?t=""#));
+        assert!(snippet.contains(r#"fetch("/validate'\\", {"#));
+        assert_eq!(snippet.matches("<script").count(), 2);
+        assert_eq!(snippet.matches("</script>").count(), 2);
+    }
+
+    #[test]
+    fn demo_page_renders_paths() {
+        let assets = Assets {
+            gt_js_path: "/assets/gt.js".to_owned(),
+            demo_title: "Captcha <demo>".to_owned(),
+            register_path: "/api/register".to_owned(),
+            validate_path: "/api/validate".to_owned(),
+            product: "popup".to_owned(),
+            ..Assets::default()
+        };
+        let page = assets.demo_page();
+
+        assert!(page.contains("<title>Captcha &lt;demo&gt;</title>"));
+        assert!(page.contains(r#"<script src="/assets/gt.js"></script>"#));
+        assert!(page.contains(r#"fetch("/api/register" + "?t=""#));
+        assert!(page.contains(r#"fetch("/api/validate", {"#));
+        assert!(page.contains(r#"product: "popup","#));
+        assert!(page.contains(r##"captchaObj.appendTo("#captcha");"##));
+    }
+}
@@ -0,0 +1,202 @@
+//! GeeTest API calls made by [`Handler`], and an in-memory fake to test code built on it without network.
+//!
+//! ```
+//! use geetest::{backend::FakeBackend, CaptchaSecret, DigestMod, Handler};
+//!
+//! let fake = FakeBackend::new("geetest-captcha-id", DigestMod::Md5);
+//! let digest = |origin: &str, _: &CaptchaSecret| origin.to_owned();
+//! let handler = Handler::with_digest(fake.clone(), "geetest-captcha-secret", digest);
+//!
+//! fake.approve("good-seccode").reject("bad-seccode");
+//! fake.set_online(false); // switch to fail-back mode
+//! ```
+//!
+//! [`Handler`]: crate::server::Handler
+
+#[cfg(feature = "runtime")]
+use crate::client::Client;
+use crate::{
+    error::Error,
+    models::{DigestMod, UserInfo},
+};
+use futures_util::future::{self, BoxFuture};
+use std::{
+    collections::HashMap,
+    io,
+    sync::{Arc, Mutex},
+};
+
+/// GeeTest API calls made by [`Handler`], implemented by [`Client`].
+///
+/// [`Handler`]: crate::server::Handler
+pub trait CaptchaBackend: Send + Sync + 'static {
+    fn captcha_id(&self) -> &str;
+
+    fn digestmod(&self) -> DigestMod;
+
+    fn bypass_status(&self) -> BoxFuture<'_, Result<bool, Error>>;
+
+    /// Returns the origin challenge, `None` if GeeTest asks for fail-back mode.
+    fn register(&self, user_info: UserInfo) -> BoxFuture<'_, Result<Option<String>, Error>>;
+
+    /// Returns `None` if the captcha was not passed.
+    fn validate(
+        &self,
+        seccode: String,
+        challenge: String,
+        user_info: UserInfo,
+    ) -> BoxFuture<'_, Result<Option<String>, Error>>;
+}
+
+#[cfg(feature = "runtime")]
+impl CaptchaBackend for Client {
+    fn captcha_id(&self) -> &str {
+        Client::captcha_id(self)
+    }
+
+    fn digestmod(&self) -> DigestMod {
+        Client::digestmod(self)
+    }
+
+    fn bypass_status(&self) -> BoxFuture<'_, Result<bool, Error>> {
+        Box::pin(Client::bypass_status(self))
+    }
+
+    fn register(&self, user_info: UserInfo) -> BoxFuture<'_, Result<Option<String>, Error>> {
+        Box::pin(Client::register(self, user_info))
+    }
+
+    fn validate(
+        &self,
+        seccode: String,
+        challenge: String,
+        user_info: UserInfo,
+    ) -> BoxFuture<'_, Result<Option<String>, Error>> {
+        Box::pin(Client::validate(self, seccode, challenge, user_info))
+    }
+}
+
+struct FakeState {
+    online: bool,
+    unavailable: bool,
+    approve_by_default: bool,
+    verdicts: HashMap<String, bool>,
+    registered: u64,
+}
+
+/// In-memory backend for tests, no network calls are made.
+///
+/// Online and approving every submission by default. Clones share state, so a clone kept by the test can change
+/// the behaviour of a [`Handler`] built from another one.
+///
+/// [`Handler`]: crate::server::Handler
+#[derive(Clone)]
+pub struct FakeBackend {
+    captcha_id: String,
+    digestmod: DigestMod,
+    state: Arc<Mutex<FakeState>>,
+}
+
+impl FakeBackend {
+    pub fn new(captcha_id: impl Into<String>, digestmod: DigestMod) -> Self {
+        Self {
+            captcha_id: captcha_id.into(),
+            digestmod,
+            state: Arc::new(Mutex::new(FakeState {
+                online: true,
+                unavailable: false,
+                approve_by_default: true,
+                verdicts: HashMap::new(),
+                registered: 0,
+            })),
+        }
+    }
+
+    /// `false` makes bypass status ask for fail-back mode.
+    pub fn set_online(&self, online: bool) -> &Self {
+        self.lock().online = online;
+        self
+    }
+
+    /// `true` makes every call fail with a connection error.
+    pub fn set_unavailable(&self, unavailable: bool) -> &Self {
+        self.lock().unavailable = unavailable;
+        self
+    }
+
+    /// Verdict for seccodes without an explicit [`FakeBackend::approve`] or [`FakeBackend::reject`].
+    pub fn approve_by_default(&self, approve: bool) -> &Self {
+        self.lock().approve_by_default = approve;
+        self
+    }
+
+    pub fn approve(&self, seccode: impl Into<String>) -> &Self {
+        self.lock().verdicts.insert(seccode.into(), true);
+        self
+    }
+
+    pub fn reject(&self, seccode: impl Into<String>) -> &Self {
+        self.lock().verdicts.insert(seccode.into(), false);
+        self
+    }
+
+    /// Number of register calls made. Origin challenges are `fake-challenge-0`, `fake-challenge-1`, etc.
+    pub fn registered(&self) -> u64 {
+        self.lock().registered
+    }
+
+    fn check_available(state: &FakeState) -> Result<(), Error> {
+        if state.unavailable {
+            return Err(io::Error::new(io::ErrorKind::ConnectionRefused, "fake backend is unavailable").into());
+        }
+        Ok(())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, FakeState> {
+        self.state.lock().unwrap_or_else(|error| error.into_inner())
+    }
+}
+
+impl CaptchaBackend for FakeBackend {
+    fn captcha_id(&self) -> &str {
+        &self.captcha_id
+    }
+
+    fn digestmod(&self) -> DigestMod {
+        self.digestmod
+    }
+
+    fn bypass_status(&self) -> BoxFuture<'_, Result<bool, Error>> {
+        let state = self.lock();
+        let result = Self::check_available(&state).map(|_| state.online);
+        Box::pin(future::ready(result))
+    }
+
+    fn register(&self, _user_info: UserInfo) -> BoxFuture<'_, Result<Option<String>, Error>> {
+        let mut state = self.lock();
+        let result = Self::check_available(&state).map(|_| {
+            let challenge = format!("fake-challenge-{}", state.registered);
+            state.registered += 1;
+            Some(challenge)
+        });
+        Box::pin(future::ready(result))
+    }
+
+    fn validate(
+        &self,
+        seccode: String,
+        _challenge: String,
+        _user_info: UserInfo,
+    ) -> BoxFuture<'_, Result<Option<String>, Error>> {
+        let state = self.lock();
+        let result = Self::check_available(&state).map(|_| {
+            let approved = state
+                .verdicts
+                .get(&seccode)
+                .copied()
+                .unwrap_or(state.approve_by_default);
+            approved.then_some(seccode)
+        });
+        Box::pin(future::ready(result))
+    }
+}
@@ -0,0 +1,296 @@
+use clap::{error::ErrorKind, Args, CommandFactory, Parser, Subcommand};
+use geetest::{digest, ClientType, DigestMod, Error, GeetestConfig, UserInfo};
+use serde_json::json;
+use std::{net::IpAddr, path::PathBuf, process::ExitCode};
+
+/// GeeTest captcha operator tool.
+///
+/// Configuration is read from `--config` file or `GEETEST_*` environment variables, command line flags take
+/// precedence.
+#[derive(Parser)]
+#[command(name = "geetest", version)]
+struct Cli {
+    #[command(flatten)]
+    global: GlobalArgs,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Args)]
+struct GlobalArgs {
+    /// JSON or TOML configuration file.
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    #[arg(long, global = true)]
+    captcha_id: Option<String>,
+    /// Required with `--captcha-id` unless `--config` is given.
+    #[arg(long, global = true, env = "GEETEST_CAPTCHA_SECRET", hide_env_values = true)]
+    captcha_secret: Option<String>,
+    #[arg(long, global = true)]
+    digestmod: Option<DigestMod>,
+    /// Print JSON instead of human-readable output.
+    #[arg(long, global = true)]
+    json: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Check whether GeeTest is online or asks to use fail-back mode.
+    Status,
+    /// Request a new challenge from GeeTest.
+    Register(UserArgs),
+    /// Check a solved captcha with GeeTest.
+    Validate {
+        #[arg(long)]
+        challenge: String,
+        #[arg(long)]
+        seccode: String,
+        #[command(flatten)]
+        user: UserArgs,
+    },
+    /// Run the captcha server.
+    Serve {
+        #[arg(long, default_value = "127.0.0.1:8000")]
+        addr: String,
+        #[arg(long)]
+        failback_on_error: bool,
+        #[arg(long)]
+        trust_forwarded_for: bool,
+        #[arg(long)]
+        ready_in_failback: bool,
+    },
+    /// Compute the challenge digest sent to the browser for an origin challenge.
+    Digest {
+        #[arg(long)]
+        challenge: String,
+    },
+}
+
+#[derive(Args)]
+struct UserArgs {
+    #[arg(long)]
+    user_id: Option<String>,
+    #[arg(long)]
+    ip_address: Option<IpAddr>,
+    #[arg(long)]
+    client_type: Option<ClientType>,
+}
+
+impl UserArgs {
+    fn user_info(self) -> UserInfo {
+        UserInfo {
+            user_id: self.user_id,
+            client_type: self.client_type,
+            ip_address: self.ip_address,
+        }
+    }
+}
+
+impl GlobalArgs {
+    fn config(&self) -> Result<GeetestConfig, Error> {
+        let mut config = match (&self.config, &self.captcha_id, &self.captcha_secret) {
+            (Some(path), ..) => GeetestConfig::from_file(path)?,
+            (None, Some(captcha_id), Some(captcha_secret)) => {
+                GeetestConfig::new(captcha_id.clone(), captcha_secret.clone())
+            },
+            (None, ..) => GeetestConfig::from_env()?,
+        };
+        if let Some(captcha_id) = &self.captcha_id {
+            config.captcha_id = captcha_id.clone();
+        }
+        if let Some(captcha_secret) = &self.captcha_secret {
+            config.captcha_secret = captcha_secret.clone().into();
+        }
+        if let Some(digestmod) = self.digestmod {
+            config.digestmod = digestmod;
+        }
+        Ok(config)
+    }
+
+    fn check(&self) -> Result<(), clap::Error> {
+        if self.config.is_none() && self.captcha_id.is_some() && self.captcha_secret.is_none() {
+            return Err(Cli::command().error(
+                ErrorKind::MissingRequiredArgument,
+                "--captcha-secret (or GEETEST_CAPTCHA_SECRET) is required with --captcha-id unless --config is given",
+            ));
+        }
+        Ok(())
+    }
+
+    fn render(&self, value: serde_json::Value, human: String) -> String {
+        if self.json {
+            value.to_string()
+        } else {
+            human
+        }
+    }
+
+    fn print(&self, value: serde_json::Value, human: String) {
+        println!("{}", self.render(value, human));
+    }
+
+    fn digest(&self, challenge: &str) -> Result<String, Error> {
+        // Captcha id is not needed, so don't require it when the secret is given.
+        let (digestmod, captcha_secret) = match &self.captcha_secret {
+            Some(captcha_secret) => (self.digestmod.unwrap_or_default(), captcha_secret.clone().into()),
+            None => {
+                let config = self.config()?;
+                (config.digestmod, config.captcha_secret)
+            },
+        };
+        let digest = digest::builtin(digestmod)
+            .ok_or_else(|| Error::Config(format!("Digest {} is disabled", digestmod)))?
+            .digest(challenge, &captcha_secret);
+        Ok(self.render(
+            json!({ "digestmod": digestmod, "challenge": digest }),
+            format!("{} digest: {}", digestmod, digest),
+        ))
+    }
+}
+
+impl Cli {
+    async fn run(self) -> Result<ExitCode, Error> {
+        let Cli { global, command } = self;
+        match command {
+            Command::Status => {
+                let status = global.config()?.client().bypass_status().await?;
+                let mode = if status { "online" } else { "fail-back" };
+                global.print(
+                    json!({ "status": status, "mode": mode }),
+                    format!("GeeTest status: {}", mode),
+                );
+            },
+            Command::Register(user) => {
+                let config = global.config()?;
+                let origin_challenge = match config.client().register(user.user_info()).await? {
+                    Some(origin_challenge) => origin_challenge,
+                    None => {
+                        global.print(
+                            json!({ "origin_challenge": null, "mode": "fail-back" }),
+                            "GeeTest returned no challenge, use fail-back mode".to_owned(),
+                        );
+                        return Ok(ExitCode::FAILURE);
+                    },
+                };
+                let challenge = digest::builtin(config.digestmod)
+                    .map(|digest| digest.digest(&origin_challenge, &config.captcha_secret));
+                global.print(
+                    json!({ "origin_challenge": origin_challenge, "challenge": challenge }),
+                    format!(
+                        "Origin challenge: {}\nChallenge: {}",
+                        origin_challenge,
+                        challenge.as_deref().unwrap_or("<digest disabled>")
+                    ),
+                );
+            },
+            Command::Validate {
+                challenge,
+                seccode,
+                user,
+            } => {
+                let config = global.config()?;
+                let seccode = config.client().validate(seccode, challenge, user.user_info()).await?;
+                let verdict = if seccode.is_some() { "valid" } else { "invalid" };
+                global.print(
+                    json!({ "valid": seccode.is_some(), "seccode": seccode }),
+                    format!("Captcha is {}", verdict),
+                );
+                if seccode.is_none() {
+                    return Ok(ExitCode::FAILURE);
+                }
+            },
+            Command::Serve {
+                addr,
+                failback_on_error,
+                trust_forwarded_for,
+                ready_in_failback,
+            } => {
+                let mut config = global.config()?;
+                config.failback_on_error |= failback_on_error;
+                config.trust_forwarded_for |= trust_forwarded_for;
+                config.ready_in_failback |= ready_in_failback;
+                tracing::info!("Running server at http://{}", addr);
+                config.server()?.run(addr.as_str()).await?;
+            },
+            Command::Digest { challenge } => println!("{}", global.digest(&challenge)?),
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    env_logger::init();
+
+    let cli = Cli::parse();
+    if let Err(error) = cli.global.check() {
+        error.exit();
+    }
+    let json = cli.global.json;
+    match cli.run().await {
+        Ok(code) => code,
+        Err(error) if json => {
+            eprintln!("{}", json!({ "error": error.to_string() }));
+            ExitCode::from(2)
+        },
+        Err(error) => {
+            eprintln!("error: {}", error);
+            ExitCode::from(2)
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        let cli = Cli::try_parse_from(Some("geetest").iter().chain(args))?;
+        cli.global.check()?;
+        Ok(cli)
+    }
+
+    #[test]
+    fn captcha_id_needs_secret() {
+        std::env::remove_var("GEETEST_CAPTCHA_SECRET");
+
+        let error = parse(&["status", "--captcha-id", "id"]).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::MissingRequiredArgument);
+        assert!(parse(&["status", "--captcha-id", "id", "--captcha-secret", "secret"]).is_ok());
+        assert!(parse(&["status", "--captcha-id", "id", "--config", "geetest.toml"]).is_ok());
+        assert!(parse(&["status"]).is_ok());
+
+        let error = parse(&["register", "--ip-address", "not-an-ip"]).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::ValueValidation);
+        let error = parse(&["validate", "--challenge", "challenge"]).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::MissingRequiredArgument);
+    }
+
+    #[test]
+    fn digest_matches_builtin() {
+        for digestmod in ["md5", "sha256", "hmac-sha256"].iter() {
+            let parsed: DigestMod = digestmod.parse().unwrap();
+            let expected = match digest::builtin(parsed) {
+                Some(digest) => digest.digest("origin-challenge", &"secret".into()),
+                None => continue,
+            };
+
+            let args = [
+                "digest",
+                "--challenge",
+                "origin-challenge",
+                "--captcha-secret",
+                "secret",
+                "--digestmod",
+            ];
+            let cli = parse(&[&args[..], &[digestmod]].concat()).unwrap();
+            let human = cli.global.digest("origin-challenge").unwrap();
+            assert_eq!(human, format!("{} digest: {}", digestmod, expected));
+
+            let cli = parse(&[&args[..], &[digestmod, "--json"]].concat()).unwrap();
+            let output: serde_json::Value =
+                serde_json::from_str(&cli.global.digest("origin-challenge").unwrap()).unwrap();
+            assert_eq!(output, json!({ "digestmod": digestmod, "challenge": expected }));
+        }
+    }
+}
@@ -0,0 +1,361 @@
+//! Circuit breaker for GeeTest API calls.
+//!
+//! Opens after a number of consecutive failed or slow calls. While it is open, [`Handler`] issues fail-back
+//! challenges without calling GeeTest; after the cooldown a single probe call decides whether to close it again.
+//!
+//! ```
+//! use geetest::breaker::{CircuitBreaker, CircuitBreakerConfig};
+//!
+//! let breaker = CircuitBreaker::new(CircuitBreakerConfig {
+//!     failure_threshold: 5,
+//!     slow_call_ms: Some(2000),
+//!     cooldown_ms: 30_000,
+//! })
+//! .on_state_change(|transition| eprintln!("circuit {} -> {}", transition.from, transition.to));
+//! ```
+//!
+//! [`Handler`]: crate::server::Handler
+
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    fmt,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CircuitState {
+    /// GeeTest API calls go through.
+    Closed,
+    /// GeeTest API is considered down, calls are rejected until cooldown expires.
+    Open,
+    /// Cooldown expired, a single probe call is let through to decide on closing the circuit.
+    HalfOpen,
+}
+
+impl CircuitState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CircuitState::Closed => "closed",
+            CircuitState::Open => "open",
+            CircuitState::HalfOpen => "half-open",
+        }
+    }
+}
+
+impl fmt::Display for CircuitState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CircuitBreakerConfig {
+    /// Consecutive failed (or slow) calls that open the circuit.
+    pub failure_threshold: u32,
+    /// Calls taking longer than this are counted as failures.
+    pub slow_call_ms: Option<u64>,
+    /// How long the circuit stays open before a probe call is allowed.
+    pub cooldown_ms: u64,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            slow_call_ms: None,
+            cooldown_ms: 30_000,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CircuitTransition {
+    pub from: CircuitState,
+    pub to: CircuitState,
+}
+
+type Listener = Box<dyn Fn(&CircuitTransition) + Send + Sync>;
+
+/// Permission to make a call, returned by [`CircuitBreaker::try_acquire`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CircuitPermit {
+    generation: u64,
+}
+
+struct Inner {
+    state: CircuitState,
+    consecutive_failures: u32,
+    changed_at: Instant,
+    probe_started: Option<Instant>,
+    /// Bumped on every state change and probe, results of calls acquired in an older generation are ignored.
+    generation: u64,
+}
+
+/// Circuit breaker for GeeTest API calls, used by [`Handler`] to switch into fail-back mode while GeeTest is
+/// degraded.
+///
+/// [`Handler`]: crate::server::Handler
+pub struct CircuitBreaker {
+    config: CircuitBreakerConfig,
+    inner: Mutex<Inner>,
+    listeners: Vec<Listener>,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        Self {
+            config,
+            inner: Mutex::new(Inner {
+                state: CircuitState::Closed,
+                consecutive_failures: 0,
+                changed_at: Instant::now(),
+                probe_started: None,
+                generation: 0,
+            }),
+            listeners: Vec::new(),
+        }
+    }
+
+    /// Calls `listener` on every state change.
+    pub fn on_state_change(mut self, listener: impl Fn(&CircuitTransition) + Send + Sync + 'static) -> Self {
+        self.listeners.push(Box::new(listener));
+        self
+    }
+
+    pub fn config(&self) -> &CircuitBreakerConfig {
+        &self.config
+    }
+
+    /// An open circuit is reported half-open once its cooldown expired, as the next call is let through as a probe.
+    pub fn state(&self) -> CircuitState {
+        let inner = self.lock();
+        match inner.state {
+            CircuitState::Open if inner.changed_at.elapsed() >= self.cooldown() => CircuitState::HalfOpen,
+            state => state,
+        }
+    }
+
+    /// Returns `None` if the call must not be made.
+    pub fn try_acquire(&self) -> Option<CircuitPermit> {
+        let cooldown = self.cooldown();
+        let mut inner = self.lock();
+        let transition = match inner.state {
+            CircuitState::Closed => {
+                return Some(CircuitPermit {
+                    generation: inner.generation,
+                })
+            },
+            CircuitState::Open if inner.changed_at.elapsed() < cooldown => return None,
+            CircuitState::Open => Self::transition(&mut inner, CircuitState::HalfOpen),
+            CircuitState::HalfOpen => {
+                // Let another probe through if the previous one was abandoned.
+                if inner.probe_started.is_some_and(|started| started.elapsed() < cooldown) {
+                    return None;
+                }
+                inner.generation += 1;
+                None
+            },
+        };
+        inner.probe_started = Some(Instant::now());
+        let permit = CircuitPermit {
+            generation: inner.generation,
+        };
+        drop(inner);
+
+        self.notify(transition);
+        Some(permit)
+    }
+
+    /// Records the result of a call allowed by [`CircuitBreaker::try_acquire`].
+    ///
+    /// Results of calls started before the last state change, or of abandoned probes, are ignored.
+    pub fn record(&self, permit: CircuitPermit, success: bool, latency: Duration) {
+        let slow = self
+            .config
+            .slow_call_ms
+            .is_some_and(|slow_call_ms| latency > Duration::from_millis(slow_call_ms));
+        let failed = !success || slow;
+
+        let mut inner = self.lock();
+        if permit.generation != inner.generation {
+            return;
+        }
+        let transition = match (inner.state, failed) {
+            (CircuitState::Closed, false) => {
+                inner.consecutive_failures = 0;
+                None
+            },
+            (CircuitState::Closed, true) => {
+                inner.consecutive_failures += 1;
+                if inner.consecutive_failures >= self.config.failure_threshold {
+                    Self::transition(&mut inner, CircuitState::Open)
+                } else {
+                    None
+                }
+            },
+            (CircuitState::HalfOpen, false) => Self::transition(&mut inner, CircuitState::Closed),
+            (CircuitState::HalfOpen, true) => Self::transition(&mut inner, CircuitState::Open),
+            (CircuitState::Open, _) => None,
+        };
+        drop(inner);
+
+        self.notify(transition);
+    }
+
+    fn cooldown(&self) -> Duration {
+        Duration::from_millis(self.config.cooldown_ms)
+    }
+
+    fn transition(inner: &mut Inner, to: CircuitState) -> Option<CircuitTransition> {
+        let from = inner.state;
+        inner.state = to;
+        inner.changed_at = Instant::now();
+        inner.consecutive_failures = 0;
+        inner.probe_started = None;
+        inner.generation += 1;
+        Some(CircuitTransition { from, to })
+    }
+
+    fn notify(&self, transition: Option<CircuitTransition>) {
+        if let Some(transition) = transition {
+            tracing::warn!(
+                from = transition.from.as_str(),
+                to = transition.to.as_str(),
+                "geetest circuit state changed"
+            );
+            for listener in &self.listeners {
+                listener(&transition);
+            }
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|error| error.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::Arc, thread::sleep};
+
+    const COOLDOWN: Duration = Duration::from_millis(50);
+
+    fn breaker(failure_threshold: u32) -> (CircuitBreaker, Arc<Mutex<Vec<CircuitState>>>) {
+        let states = Arc::new(Mutex::new(Vec::new()));
+        let breaker = CircuitBreaker::new(CircuitBreakerConfig {
+            failure_threshold,
+            slow_call_ms: Some(1000),
+            cooldown_ms: COOLDOWN.as_millis() as u64,
+        })
+        .on_state_change({
+            let states = states.clone();
+            move |transition| states.lock().unwrap().push(transition.to)
+        });
+        (breaker, states)
+    }
+
+    fn call(breaker: &CircuitBreaker, success: bool) {
+        let permit = breaker.try_acquire().expect("call allowed");
+        breaker.record(permit, success, Duration::ZERO);
+    }
+
+    #[test]
+    fn opens_after_consecutive_failures() {
+        let (breaker, states) = breaker(3);
+        call(&breaker, false);
+        call(&breaker, false);
+        call(&breaker, true);
+        call(&breaker, false);
+        call(&breaker, false);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+
+        let permit = breaker.try_acquire().unwrap();
+        breaker.record(permit, true, Duration::from_secs(2));
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert_eq!(breaker.try_acquire(), None);
+        assert_eq!(*states.lock().unwrap(), [CircuitState::Open]);
+    }
+
+    #[test]
+    fn half_open_probe_closes_or_reopens() {
+        let (breaker, states) = breaker(1);
+        call(&breaker, false);
+        assert_eq!(breaker.try_acquire(), None);
+
+        sleep(COOLDOWN);
+        let probe = breaker.try_acquire().expect("probe allowed");
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert_eq!(breaker.try_acquire(), None, "a single probe at a time");
+        breaker.record(probe, false, Duration::ZERO);
+        assert_eq!(breaker.state(), CircuitState::Open);
+
+        sleep(COOLDOWN);
+        call(&breaker, true);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert_eq!(*states.lock().unwrap(), [
+            CircuitState::Open,
+            CircuitState::HalfOpen,
+            CircuitState::Open,
+            CircuitState::HalfOpen,
+            CircuitState::Closed
+        ]);
+    }
+
+    #[test]
+    fn reported_half_open_after_cooldown() {
+        let (breaker, states) = breaker(1);
+        call(&breaker, false);
+        assert_eq!(breaker.state(), CircuitState::Open);
+
+        sleep(COOLDOWN);
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert_eq!(
+            *states.lock().unwrap(),
+            [CircuitState::Open],
+            "no transition until probed"
+        );
+
+        call(&breaker, true);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+
+    #[test]
+    fn stale_results_ignored() {
+        let (breaker, _) = breaker(1);
+        let slow_call = breaker.try_acquire().unwrap();
+        call(&breaker, false);
+
+        sleep(COOLDOWN);
+        let probe = breaker.try_acquire().unwrap();
+        // A call started before the trip must not close the circuit.
+        breaker.record(slow_call, true, Duration::ZERO);
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+
+        breaker.record(probe, true, Duration::ZERO);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+
+        // Nor open it again once closed.
+        breaker.record(slow_call, false, Duration::ZERO);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+
+    #[test]
+    fn abandoned_probe_replaced() {
+        let (breaker, _) = breaker(1);
+        call(&breaker, false);
+        sleep(COOLDOWN);
+
+        let abandoned = breaker.try_acquire().unwrap();
+        sleep(COOLDOWN);
+        let probe = breaker.try_acquire().expect("new probe after cooldown");
+
+        breaker.record(abandoned, true, Duration::ZERO);
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        breaker.record(probe, false, Duration::ZERO);
+        assert_eq!(breaker.state(), CircuitState::Open);
+    }
+}
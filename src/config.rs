@@ -0,0 +1,359 @@
+//! [`GeetestConfig`] read from `GEETEST_*` environment variables, or a JSON (or TOML, with `config-toml` feature)
+//! file:
+//!
+//! ```toml
+//! captcha_id = "geetest-captcha-id"
+//! captcha_secret = "geetest-captcha-secret"
+//! digestmod = "hmac-sha256"
+//! timeout_ms = 3000
+//! failback_on_error = true
+//!
+//! [endpoints]
+//! register = "https://api.geetest.com/register.php"
+//! ```
+
+#[cfg(feature = "assets")]
+use crate::assets::Assets;
+#[cfg(feature = "session-binding")]
+use crate::session::SessionBinder;
+#[cfg(feature = "pass-token")]
+use crate::token::PassTokenIssuer;
+use crate::{
+    breaker::{CircuitBreaker, CircuitBreakerConfig},
+    client::{Client, Endpoints, PoolConfig},
+    error::Error,
+    hedge::HedgeConfig,
+    models::DigestMod,
+    proxy::ProxyConfig,
+    ratelimit::{RateLimitPolicy, RateLimiter, RateLimits},
+    secret::CaptchaSecret,
+    server::{Handler, HandlerBuilder, Server},
+};
+use serde_derive::Deserialize;
+use std::{collections::HashMap, env, net::IpAddr, path::Path, str::FromStr, time::Duration};
+
+pub static ENV_PREFIX: &str = "GEETEST_";
+
+/// Captcha service configuration.
+///
+/// Can be deserialized from any serde format (see [`GeetestConfig::from_file`]) or read from environment
+/// variables (see [`GeetestConfig::from_env`]).
+#[derive(Clone, Debug, Deserialize)]
+pub struct GeetestConfig {
+    pub captcha_id: String,
+    pub captcha_secret: CaptchaSecret,
+    #[serde(default)]
+    pub digestmod: DigestMod,
+    #[serde(default)]
+    pub endpoints: Endpoints,
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    #[serde(default)]
+    pub pool: PoolConfig,
+    /// Taken from `HTTPS_PROXY`/`NO_PROXY` environment variables if not set.
+    #[serde(default)]
+    pub proxy: Option<ProxyConfig>,
+    /// Static host name overrides, e.g. `api.geetest.com = ["10.0.0.5"]`.
+    #[serde(default)]
+    pub resolve: HashMap<String, Vec<IpAddr>>,
+    #[serde(default)]
+    pub hedge: Option<HedgeConfig>,
+    #[serde(default)]
+    pub coalesce_bypass_status: bool,
+    #[serde(default)]
+    pub failback_on_error: bool,
+    #[serde(default)]
+    pub rate_limits: Option<RateLimits>,
+    #[serde(default)]
+    pub trust_forwarded_for: bool,
+    /// Reverse proxies appending to `X-Forwarded-For` when `trust_forwarded_for` is set, 1 if not set.
+    #[serde(default)]
+    pub trusted_proxies: Option<usize>,
+    #[serde(default)]
+    pub ready_in_failback: bool,
+    #[serde(default)]
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+    #[cfg(feature = "pass-token")]
+    #[serde(default)]
+    pub pass_token: Option<PassTokenConfig>,
+    #[cfg(feature = "session-binding")]
+    #[serde(default)]
+    pub session_binding: Option<SessionBindingConfig>,
+    #[cfg(feature = "assets")]
+    #[serde(default)]
+    pub assets: Option<Assets>,
+}
+
+#[cfg(feature = "pass-token")]
+#[derive(Clone, Debug, Deserialize)]
+pub struct PassTokenConfig {
+    pub key: CaptchaSecret,
+    pub scope: String,
+    pub ttl_secs: u64,
+}
+
+#[cfg(feature = "session-binding")]
+#[derive(Clone, Debug, Deserialize)]
+pub struct SessionBindingConfig {
+    pub key: CaptchaSecret,
+    #[serde(default)]
+    pub secure_cookie: bool,
+}
+
+impl GeetestConfig {
+    pub fn new(captcha_id: impl Into<String>, captcha_secret: impl Into<CaptchaSecret>) -> Self {
+        Self {
+            captcha_id: captcha_id.into(),
+            captcha_secret: captcha_secret.into(),
+            digestmod: DigestMod::default(),
+            endpoints: Endpoints::default(),
+            timeout_ms: None,
+            pool: PoolConfig::default(),
+            proxy: None,
+            resolve: HashMap::new(),
+            hedge: None,
+            coalesce_bypass_status: false,
+            failback_on_error: false,
+            rate_limits: None,
+            trust_forwarded_for: false,
+            trusted_proxies: None,
+            ready_in_failback: false,
+            circuit_breaker: None,
+            #[cfg(feature = "pass-token")]
+            pass_token: None,
+            #[cfg(feature = "session-binding")]
+            session_binding: None,
+            #[cfg(feature = "assets")]
+            assets: None,
+        }
+    }
+
+    /// Reads `GEETEST_*` environment variables.
+    ///
+    /// `GEETEST_CAPTCHA_ID` and `GEETEST_CAPTCHA_SECRET` are required. Optional:
+    ///
+    /// - `GEETEST_DIGESTMOD`, `GEETEST_REGISTER_URL`, `GEETEST_VALIDATE_URL`, `GEETEST_STATUS_URL`,
+    ///   `GEETEST_TIMEOUT_MS`;
+    /// - `GEETEST_FAILBACK_ON_ERROR`, `GEETEST_COALESCE_BYPASS_STATUS`, `GEETEST_TRUST_FORWARDED_FOR`,
+    ///   `GEETEST_READY_IN_FAILBACK` booleans, and `GEETEST_TRUSTED_PROXIES`;
+    /// - `GEETEST_RATE_LIMIT_{REGISTER,VALIDATE}_{PER_IP,PER_USER}` as `<requests>/s` or `<requests>/min`;
+    /// - `GEETEST_CIRCUIT_BREAKER_THRESHOLD`, `GEETEST_CIRCUIT_BREAKER_COOLDOWN_MS`,
+    ///   `GEETEST_CIRCUIT_BREAKER_SLOW_CALL_MS`, any of them enables the breaker;
+    /// - `GEETEST_PASS_TOKEN_KEY` with `GEETEST_PASS_TOKEN_SCOPE` and `GEETEST_PASS_TOKEN_TTL_SECS`;
+    /// - `GEETEST_SESSION_KEY` and `GEETEST_SESSION_SECURE_COOKIE`.
+    ///
+    /// Connection pool, proxy (besides `HTTPS_PROXY`/`NO_PROXY`), DNS overrides, hedging and assets can only be
+    /// set from a file.
+    pub fn from_env() -> Result<Self, Error> {
+        Self::from_env_prefixed(ENV_PREFIX)
+    }
+
+    pub fn from_env_prefixed(prefix: &str) -> Result<Self, Error> {
+        let required = |name: &str| -> Result<String, Error> {
+            env_var(prefix, name)?.ok_or_else(|| Error::Config(format!("{}{} is not set", prefix, name)))
+        };
+
+        let mut config = Self::new(required("CAPTCHA_ID")?, required("CAPTCHA_SECRET")?);
+        if let Some(digestmod) = env_var(prefix, "DIGESTMOD")? {
+            config.digestmod = digestmod;
+        }
+        if let Some(register) = env_var(prefix, "REGISTER_URL")? {
+            config.endpoints.register = register;
+        }
+        if let Some(validate) = env_var(prefix, "VALIDATE_URL")? {
+            config.endpoints.validate = validate;
+        }
+        if let Some(status) = env_var(prefix, "STATUS_URL")? {
+            config.endpoints.status = status;
+        }
+        config.timeout_ms = env_var(prefix, "TIMEOUT_MS")?;
+
+        let flag = |name: &str| -> Result<bool, Error> { Ok(env_var(prefix, name)?.is_some_and(|EnvBool(b)| b)) };
+        config.failback_on_error = flag("FAILBACK_ON_ERROR")?;
+        config.coalesce_bypass_status = flag("COALESCE_BYPASS_STATUS")?;
+        config.trust_forwarded_for = flag("TRUST_FORWARDED_FOR")?;
+        config.ready_in_failback = flag("READY_IN_FAILBACK")?;
+        config.trusted_proxies = env_var(prefix, "TRUSTED_PROXIES")?;
+
+        let limits = RateLimits {
+            register: RateLimitPolicy {
+                per_ip: env_var(prefix, "RATE_LIMIT_REGISTER_PER_IP")?,
+                per_user: env_var(prefix, "RATE_LIMIT_REGISTER_PER_USER")?,
+            },
+            validate: RateLimitPolicy {
+                per_ip: env_var(prefix, "RATE_LIMIT_VALIDATE_PER_IP")?,
+                per_user: env_var(prefix, "RATE_LIMIT_VALIDATE_PER_USER")?,
+            },
+        };
+        if limits != RateLimits::default() {
+            config.rate_limits = Some(limits);
+        }
+
+        let threshold = env_var(prefix, "CIRCUIT_BREAKER_THRESHOLD")?;
+        let cooldown_ms = env_var(prefix, "CIRCUIT_BREAKER_COOLDOWN_MS")?;
+        let slow_call_ms = env_var(prefix, "CIRCUIT_BREAKER_SLOW_CALL_MS")?;
+        if threshold.is_some() || cooldown_ms.is_some() || slow_call_ms.is_some() {
+            let defaults = CircuitBreakerConfig::default();
+            config.circuit_breaker = Some(CircuitBreakerConfig {
+                failure_threshold: threshold.unwrap_or(defaults.failure_threshold),
+                cooldown_ms: cooldown_ms.unwrap_or(defaults.cooldown_ms),
+                slow_call_ms,
+            });
+        }
+
+        #[cfg(feature = "pass-token")]
+        if let Some(key) = env_var::<String>(prefix, "PASS_TOKEN_KEY")? {
+            config.pass_token = Some(PassTokenConfig {
+                key: key.into(),
+                scope: required("PASS_TOKEN_SCOPE")?,
+                ttl_secs: required("PASS_TOKEN_TTL_SECS")?
+                    .parse()
+                    .map_err(|_| Error::Config(format!("{}PASS_TOKEN_TTL_SECS is invalid", prefix)))?,
+            });
+        }
+        #[cfg(feature = "session-binding")]
+        if let Some(key) = env_var::<String>(prefix, "SESSION_KEY")? {
+            config.session_binding = Some(SessionBindingConfig {
+                key: key.into(),
+                secure_cookie: flag("SESSION_SECURE_COOKIE")?,
+            });
+        }
+        Ok(config)
+    }
+
+    /// Loads configuration from a `.json` or (with `config-toml` feature) `.toml` file.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => Self::from_json_str(&content),
+            #[cfg(feature = "config-toml")]
+            Some("toml") => Self::from_toml_str(&content),
+            _ => Err(Error::Config(format!(
+                "Unsupported config file format: {}",
+                path.display()
+            ))),
+        }
+    }
+
+    pub fn from_json_str(content: &str) -> Result<Self, Error> {
+        serde_json::from_str(content).map_err(Into::into)
+    }
+
+    #[cfg(feature = "config-toml")]
+    pub fn from_toml_str(content: &str) -> Result<Self, Error> {
+        toml::from_str(content).map_err(Into::into)
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout_ms.map(Duration::from_millis)
+    }
+
+    pub fn client(&self) -> Client {
+        self.build_client(self.coalesce_bypass_status)
+    }
+
+    fn build_client(&self, coalesce_bypass_status: bool) -> Client {
+        let mut builder = Client::builder(self.captcha_id.clone())
+            .digestmod(self.digestmod)
+            .endpoints(self.endpoints.clone())
+            .pool(self.pool)
+            .coalesce_bypass_status(coalesce_bypass_status);
+        if let Some(timeout) = self.timeout() {
+            builder = builder.timeout(timeout);
+        }
+
+        let proxy = match &self.proxy {
+            Some(proxy) => Some(proxy.clone()),
+            None => ProxyConfig::from_env().unwrap_or_else(|error| {
+                tracing::warn!(error = %error, "proxy environment variables ignored");
+                None
+            }),
+        };
+        if let Some(proxy) = proxy {
+            builder = builder.proxy(proxy);
+        }
+        if let Some(hedge) = self.hedge {
+            builder = builder.hedge(hedge);
+        }
+        for (host, addrs) in &self.resolve {
+            for addr in addrs {
+                builder = builder.resolve(host, *addr);
+            }
+        }
+        builder.build()
+    }
+
+    pub fn handler_builder(&self) -> Result<HandlerBuilder, Error> {
+        // Coalesced by the handler, around the circuit breaker and metrics.
+        let mut builder = Handler::builder(self.build_client(false), self.captcha_secret.clone())
+            .failback_on_error(self.failback_on_error)
+            .coalesce_bypass_status(self.coalesce_bypass_status);
+        if let Some(limits) = self.rate_limits {
+            builder = builder.rate_limiter(RateLimiter::in_memory(limits)?);
+        }
+        if let Some(circuit_breaker) = self.circuit_breaker {
+            builder = builder.circuit_breaker(CircuitBreaker::new(circuit_breaker));
+        }
+        #[cfg(feature = "pass-token")]
+        if let Some(pass_token) = &self.pass_token {
+            builder = builder.pass_token_issuer(PassTokenIssuer::new(
+                pass_token.key.clone(),
+                pass_token.scope.clone(),
+                Duration::from_secs(pass_token.ttl_secs),
+            ));
+        }
+        #[cfg(feature = "session-binding")]
+        if let Some(session_binding) = &self.session_binding {
+            builder = builder.session_binder(SessionBinder::new(session_binding.key.clone()));
+        }
+        Ok(builder)
+    }
+
+    /// Fails if the digest for `digestmod` is disabled or a rate limit is invalid.
+    pub fn handler(&self) -> Result<Handler, Error> {
+        self.handler_builder()?.build()
+    }
+
+    pub fn server(&self) -> Result<Server, Error> {
+        let server = Server::from_handler(self.handler()?)
+            .trusted_proxies(match self.trust_forwarded_for {
+                true => self.trusted_proxies.unwrap_or(1),
+                false => 0,
+            })
+            .ready_in_failback(self.ready_in_failback);
+        #[cfg(feature = "session-binding")]
+        let server = server.secure_cookie(self.session_binding.as_ref().is_some_and(|config| config.secure_cookie));
+        #[cfg(feature = "assets")]
+        let server = match &self.assets {
+            Some(assets) => server.assets(assets.clone()),
+            None => server,
+        };
+        Ok(server)
+    }
+}
+
+fn env_var<T: FromStr>(prefix: &str, name: &str) -> Result<Option<T>, Error> {
+    env::var(format!("{}{}", prefix, name))
+        .ok()
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|_| Error::Config(format!("{}{} is invalid", prefix, name)))
+        })
+        .transpose()
+}
+
+struct EnvBool(bool);
+
+impl FromStr for EnvBool {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &*s.trim().to_ascii_lowercase() {
+            "1" | "true" | "yes" | "on" => Ok(EnvBool(true)),
+            "0" | "false" | "no" | "off" | "" => Ok(EnvBool(false)),
+            _ => Err(()),
+        }
+    }
+}
@@ -0,0 +1,213 @@
+use futures_util::future::BoxFuture;
+use hyper::{
+    client::connect::{Connected, Connection},
+    service::Service,
+    Uri,
+};
+use serde_derive::Serialize;
+use std::{
+    io,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Outbound connection statistics of a [`Client`](crate::client::Client).
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Connections established since the client was built.
+    pub connections_opened: u64,
+    /// Connections currently open, either in use or idle in the pool.
+    pub connections_open: u64,
+    pub connect_errors: u64,
+}
+
+#[derive(Default)]
+pub(crate) struct PoolCounters {
+    opened: AtomicU64,
+    open: AtomicU64,
+    errors: AtomicU64,
+}
+
+impl PoolCounters {
+    pub(crate) fn stats(&self) -> PoolStats {
+        PoolStats {
+            connections_opened: self.opened.load(Ordering::Relaxed),
+            connections_open: self.open.load(Ordering::Relaxed),
+            connect_errors: self.errors.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Connector wrapper keeping [`PoolStats`].
+#[derive(Clone)]
+pub(crate) struct CountingConnector<C> {
+    inner: C,
+    counters: Arc<PoolCounters>,
+}
+
+impl<C> CountingConnector<C> {
+    pub(crate) fn new(inner: C, counters: Arc<PoolCounters>) -> Self {
+        Self { inner, counters }
+    }
+}
+
+impl<C> Service<Uri> for CountingConnector<C>
+where
+    C: Service<Uri>,
+    C::Future: Send + 'static,
+    C::Response: Send + 'static,
+    C::Error: Send + 'static,
+{
+    type Response = CountedStream<C::Response>;
+    type Error = C::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let connecting = self.inner.call(uri);
+        let counters = self.counters.clone();
+        Box::pin(async move {
+            match connecting.await {
+                Ok(stream) => {
+                    counters.opened.fetch_add(1, Ordering::Relaxed);
+                    counters.open.fetch_add(1, Ordering::Relaxed);
+                    Ok(CountedStream { stream, counters })
+                },
+                Err(error) => {
+                    counters.errors.fetch_add(1, Ordering::Relaxed);
+                    Err(error)
+                },
+            }
+        })
+    }
+}
+
+pub(crate) struct CountedStream<S> {
+    stream: S,
+    counters: Arc<PoolCounters>,
+}
+
+impl<S> Drop for CountedStream<S> {
+    fn drop(&mut self) {
+        self.counters.open.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl<S: Connection> Connection for CountedStream<S> {
+    fn connected(&self) -> Connected {
+        self.stream.connected()
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for CountedStream<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for CountedStream<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.stream.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::{
+        client::HttpConnector,
+        service::{make_service_fn, service_fn},
+        Body, Client, Response, Server,
+    };
+    use std::{convert::Infallible, net::SocketAddr};
+
+    async fn local_server() -> SocketAddr {
+        let make_service = make_service_fn(|_| async {
+            Ok::<_, Infallible>(service_fn(|_| async {
+                Ok::<_, Infallible>(Response::new(Body::from("ok")))
+            }))
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        addr
+    }
+
+    fn counting_client() -> (Client<CountingConnector<HttpConnector>>, Arc<PoolCounters>) {
+        let counters = Arc::new(PoolCounters::default());
+        let connector = CountingConnector::new(HttpConnector::new(), counters.clone());
+        (Client::builder().build(connector), counters)
+    }
+
+    #[tokio::test]
+    async fn pooled_connection_counted_once() {
+        let addr = local_server().await;
+        let (client, counters) = counting_client();
+
+        for _ in 0..3 {
+            let uri = format!("http://{}/", addr).parse().unwrap();
+            let response = client.get(uri).await.unwrap();
+            hyper::body::to_bytes(response.into_body()).await.unwrap();
+        }
+        assert_eq!(counters.stats(), PoolStats {
+            connections_opened: 1,
+            connections_open: 1,
+            connect_errors: 0,
+        });
+
+        // The connection task closes the idle connection once the pool is gone.
+        drop(client);
+        for _ in 0..100 {
+            if counters.stats().connections_open == 0 {
+                return;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        panic!("connection still open: {:?}", counters.stats());
+    }
+
+    #[tokio::test]
+    async fn connect_errors_counted() {
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let (client, counters) = counting_client();
+
+        let uri = format!("http://{}/", addr).parse().unwrap();
+        assert!(client.get(uri).await.is_err());
+        assert_eq!(counters.stats(), PoolStats {
+            connections_opened: 0,
+            connections_open: 0,
+            connect_errors: 1,
+        });
+    }
+}
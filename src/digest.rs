@@ -0,0 +1,124 @@
+//! Challenge digests and fingerprints.
+//!
+//! [`Handler`] never compares digests itself: the digested challenge is handed to the browser and checked by
+//! GeeTest together with the seccode on validate. Code that checks a challenge locally, e.g. in a custom fail-back
+//! flow, should use [`ChallengeDigest::verify`], which compares in constant time.
+//!
+//! [`Handler`]: crate::server::Handler
+
+use crate::{models::DigestMod, secret::CaptchaSecret};
+use std::sync::Arc;
+use subtle::ConstantTimeEq;
+
+/// Algorithm used to derive the challenge handed to the browser from the origin challenge returned by GeeTest.
+///
+/// Built-in implementations are provided for every [`DigestMod`] behind the corresponding `digest-*` feature,
+/// custom implementations (e.g. backed by an HSM) can be passed to [`Handler::with_digest`]:
+///
+/// ```
+/// use geetest::{backend::FakeBackend, CaptchaSecret, DigestMod, Handler};
+///
+/// let backend = FakeBackend::new("geetest-captcha-id", DigestMod::Sha256);
+/// let handler = Handler::with_digest(backend, "geetest-captcha-secret", |origin: &str, secret: &CaptchaSecret| {
+///     format!("{}{}", origin, secret.expose_secret().len())
+/// });
+/// ```
+///
+/// [`Handler::with_digest`]: crate::server::Handler::with_digest
+pub trait ChallengeDigest: Send + Sync + 'static {
+    fn digest(&self, origin_challenge: &str, secret: &CaptchaSecret) -> String;
+
+    /// Whether `challenge` is the digest of `origin_challenge`, compared in constant time.
+    fn verify(&self, origin_challenge: &str, secret: &CaptchaSecret, challenge: &str) -> bool {
+        self.digest(origin_challenge, secret)
+            .as_bytes()
+            .ct_eq(challenge.as_bytes())
+            .into()
+    }
+}
+
+impl<F> ChallengeDigest for F
+where
+    F: Fn(&str, &CaptchaSecret) -> String + Send + Sync + 'static,
+{
+    fn digest(&self, origin_challenge: &str, secret: &CaptchaSecret) -> String {
+        self(origin_challenge, secret)
+    }
+}
+
+#[cfg(feature = "digest-md5")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Md5Digest;
+
+#[cfg(feature = "digest-md5")]
+impl ChallengeDigest for Md5Digest {
+    fn digest(&self, origin_challenge: &str, secret: &CaptchaSecret) -> String {
+        let mut hasher = md5::Context::new();
+        hasher.consume(origin_challenge);
+        hasher.consume(secret.expose_secret());
+        format!("{:x}", hasher.compute())
+    }
+}
+
+#[cfg(feature = "digest-sha256")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Sha256Digest;
+
+#[cfg(feature = "digest-sha256")]
+impl ChallengeDigest for Sha256Digest {
+    fn digest(&self, origin_challenge: &str, secret: &CaptchaSecret) -> String {
+        use sha2::Digest;
+        let mut hasher = sha2::Sha256::new();
+        hasher.update(origin_challenge);
+        hasher.update(secret.expose_secret());
+        format!("{:x}", hasher.finalize())
+    }
+}
+
+#[cfg(feature = "digest-hmac-sha256")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HmacSha256Digest;
+
+#[cfg(feature = "digest-hmac-sha256")]
+impl ChallengeDigest for HmacSha256Digest {
+    fn digest(&self, origin_challenge: &str, secret: &CaptchaSecret) -> String {
+        use hmac::{Mac, NewMac};
+        // Keyed with the secret, as in official SDKs.
+        let mut hasher = hmac::Hmac::<sha2::Sha256>::new_from_slice(secret.expose_secret().as_bytes())
+            .expect("HMAC can take key of any size");
+        hasher.update(origin_challenge.as_bytes());
+        format!("{:x}", hasher.finalize().into_bytes())
+    }
+}
+
+/// Built-in digest for `digestmod`, or `None` if the corresponding feature is disabled.
+pub fn builtin(digestmod: DigestMod) -> Option<Arc<dyn ChallengeDigest>> {
+    match digestmod {
+        #[cfg(feature = "digest-md5")]
+        DigestMod::Md5 => Some(Arc::new(Md5Digest)),
+        #[cfg(feature = "digest-sha256")]
+        DigestMod::Sha256 => Some(Arc::new(Sha256Digest)),
+        #[cfg(feature = "digest-hmac-sha256")]
+        DigestMod::HmacSha256 => Some(Arc::new(HmacSha256Digest)),
+        #[allow(unreachable_patterns)]
+        _ => None,
+    }
+}
+
+/// Stable 64-bit FNV-1a fingerprint of a challenge, used in logs and traces instead of the raw value.
+///
+/// Lets register and validate calls of the same captcha be correlated across services.
+pub fn challenge_hash(challenge: &str) -> String {
+    format!("{:016x}", fnv1a(challenge.as_bytes()))
+}
+
+/// 64-bit FNV-1a, `const` so static assets can be hashed at compile time.
+pub(crate) const fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    let mut i = 0;
+    while i < bytes.len() {
+        hash = (hash ^ bytes[i] as u64).wrapping_mul(0x0100_0000_01b3);
+        i += 1;
+    }
+    hash
+}
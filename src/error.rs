@@ -1,9 +1,11 @@
+use std::sync::Arc;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("HTTP error: {0}")]
-    Http(#[from] hyper::http::Error),
+    Http(#[from] http::Error),
+    #[cfg(feature = "runtime")]
     #[error("Hyper error: {0}")]
     Hyper(#[from] hyper::Error),
     #[error("JSON error: {0}")]
@@ -11,7 +13,59 @@ pub enum Error {
     #[error("Query error: {0}")]
     Query(#[from] serde_qs::Error),
     #[error("Invalid URL: {0}")]
-    Url(#[from] hyper::http::uri::InvalidUri),
+    Url(#[from] http::uri::InvalidUri),
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
+    #[error("Request timed out after {0:?}")]
+    Timeout(std::time::Duration),
+    #[error("Rate limit exceeded, retry after {0:?}")]
+    RateLimited(std::time::Duration),
+    #[error("GeeTest API circuit is open")]
+    CircuitOpen,
+    #[error("Configuration error: {0}")]
+    Config(String),
+    /// Error of a GeeTest API call shared by concurrent callers.
+    #[error(transparent)]
+    Shared(Arc<Error>),
+    #[cfg(feature = "config-toml")]
+    #[error("TOML error: {0}")]
+    Toml(#[from] toml::de::Error),
+    #[cfg(feature = "metrics")]
+    #[error("Metrics error: {0}")]
+    Metrics(#[from] prometheus::Error),
+}
+
+impl Error {
+    /// The error behind [`Error::Shared`], or `self` for other variants.
+    pub fn unshared(&self) -> &Error {
+        match self {
+            Error::Shared(error) => error.unshared(),
+            error => error,
+        }
+    }
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("Unknown {kind}: {value}")]
+pub struct ParseError {
+    pub kind: &'static str,
+    pub value: String,
+}
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PassTokenError {
+    #[error("Malformed pass token")]
+    Malformed,
+    #[error("Invalid pass token signature")]
+    BadSignature,
+    #[error("Pass token expired")]
+    Expired,
+    #[error("Pass token scope mismatch")]
+    ScopeMismatch,
+    #[error("Pass token user mismatch")]
+    UserMismatch,
+    #[error("Pass token IP address mismatch")]
+    IpMismatch,
+    #[error("Pass token was issued in fail-back mode")]
+    Failback,
 }
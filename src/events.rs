@@ -0,0 +1,224 @@
+//! Captcha decision events: register issued, validate passed or rejected, fail-back used, rate limited, API
+//! error.
+//!
+//! ```no_run
+//! use geetest::{
+//!     backend::FakeBackend,
+//!     events::{ChannelSink, JsonLinesSink},
+//!     DigestMod, Handler,
+//! };
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let (sink, mut events) = ChannelSink::new(1024);
+//! let backend = FakeBackend::new("geetest-captcha-id", DigestMod::Md5);
+//! let handler = Handler::builder(backend, "geetest-captcha-secret")
+//!     .event_sink(sink)
+//!     .event_sink(JsonLinesSink::open("/var/log/geetest/events.jsonl")?)
+//!     .build()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::models::{Operation, Outcome, UserInfo};
+use serde_derive::Serialize;
+use std::{
+    fs::OpenOptions,
+    io::{self, LineWriter, Write},
+    path::Path,
+    sync::mpsc::{sync_channel, SyncSender, TrySendError},
+    thread::{self, JoinHandle},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::mpsc;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "kebab-case")]
+pub enum EventKind {
+    RegisterIssued,
+    ValidatePassed,
+    ValidateRejected,
+    FailbackUsed,
+    RateLimited,
+    ApiError,
+}
+
+impl EventKind {
+    pub fn new(operation: Operation, outcome: Outcome) -> Self {
+        match (operation, outcome) {
+            (Operation::Register, Outcome::Success) => EventKind::RegisterIssued,
+            (Operation::Validate, Outcome::Success) => EventKind::ValidatePassed,
+            (_, Outcome::Rejected) => EventKind::ValidateRejected,
+            (_, Outcome::Failback) => EventKind::FailbackUsed,
+            (_, Outcome::RateLimited) => EventKind::RateLimited,
+            (_, Outcome::Error) => EventKind::ApiError,
+        }
+    }
+}
+
+/// Captcha decision made by [`Handler`].
+///
+/// [`Handler`]: crate::server::Handler
+#[derive(Serialize, Clone, Debug)]
+pub struct CaptchaEvent {
+    pub kind: EventKind,
+    pub operation: Operation,
+    pub outcome: Outcome,
+    pub captcha_id: String,
+    pub user_info: UserInfo,
+    /// Fingerprint of the challenge, see [`challenge_hash`](crate::digest::challenge_hash).
+    pub challenge_hash: Option<String>,
+    pub error: Option<String>,
+    pub timestamp_ms: u64,
+    pub latency_ms: u64,
+}
+
+impl CaptchaEvent {
+    pub(crate) fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since_epoch| since_epoch.as_millis() as u64)
+    }
+}
+
+/// Receives every captcha decision made by [`Handler`], e.g. for audit or fraud detection.
+///
+/// Called inline on the request path, so implementations must not block for long.
+///
+/// [`Handler`]: crate::server::Handler
+pub trait EventSink: Send + Sync + 'static {
+    fn emit(&self, event: &CaptchaEvent);
+}
+
+impl<F> EventSink for F
+where
+    F: Fn(&CaptchaEvent) + Send + Sync + 'static,
+{
+    fn emit(&self, event: &CaptchaEvent) {
+        self(event)
+    }
+}
+
+/// Sends events to a bounded channel. Events are dropped if the receiver lags behind.
+pub struct ChannelSink {
+    sender: mpsc::Sender<CaptchaEvent>,
+}
+
+impl ChannelSink {
+    pub fn new(capacity: usize) -> (Self, mpsc::Receiver<CaptchaEvent>) {
+        let (sender, receiver) = mpsc::channel(capacity);
+        (Self { sender }, receiver)
+    }
+}
+
+impl EventSink for ChannelSink {
+    fn emit(&self, event: &CaptchaEvent) {
+        if let Err(error) = self.sender.try_send(event.clone()) {
+            tracing::warn!(error = %error, "captcha event dropped");
+        }
+    }
+}
+
+/// Appends events to a file, one JSON object per line.
+///
+/// Lines are written by a background thread, so a slow disk doesn't block request handling. Events are dropped if
+/// more than `capacity` are waiting to be written. Pending events are written out when the sink is dropped.
+pub struct JsonLinesSink {
+    sender: Option<SyncSender<Vec<u8>>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl JsonLinesSink {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::open_with_capacity(path, 1024)
+    }
+
+    pub fn open_with_capacity(path: impl AsRef<Path>, capacity: usize) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let (sender, receiver) = sync_channel::<Vec<u8>>(capacity);
+        let writer = thread::Builder::new()
+            .name("geetest-events".to_owned())
+            .spawn(move || {
+                let mut writer = LineWriter::new(file);
+                for line in receiver {
+                    if let Err(error) = writer.write_all(&line) {
+                        tracing::warn!(error = %error, "failed to write captcha event");
+                    }
+                }
+            })?;
+        Ok(Self {
+            sender: Some(sender),
+            writer: Some(writer),
+        })
+    }
+}
+
+impl EventSink for JsonLinesSink {
+    fn emit(&self, event: &CaptchaEvent) {
+        let mut line = match serde_json::to_vec(event) {
+            Ok(line) => line,
+            Err(error) => return tracing::warn!(error = %error, "failed to serialize captcha event"),
+        };
+        line.push(b'\n');
+
+        if let Some(sender) = &self.sender {
+            match sender.try_send(line) {
+                Ok(()) => {},
+                Err(TrySendError::Full(_)) => tracing::warn!("captcha event dropped, event file writer lags behind"),
+                Err(TrySendError::Disconnected(_)) => {
+                    tracing::warn!("captcha event dropped, event file writer stopped")
+                },
+            }
+        }
+    }
+}
+
+impl Drop for JsonLinesSink {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ClientType;
+
+    fn event(latency_ms: u64) -> CaptchaEvent {
+        CaptchaEvent {
+            kind: EventKind::ValidatePassed,
+            operation: Operation::Validate,
+            outcome: Outcome::Success,
+            captcha_id: "captcha-id".to_owned(),
+            user_info: UserInfo::new().client_type(ClientType::Web),
+            challenge_hash: None,
+            error: None,
+            timestamp_ms: CaptchaEvent::now(),
+            latency_ms,
+        }
+    }
+
+    #[test]
+    fn json_lines_written_in_background() {
+        let path = std::env::temp_dir().join(format!("geetest-events-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let sink = JsonLinesSink::open(&path).unwrap();
+        for latency_ms in 0..100 {
+            sink.emit(&event(latency_ms));
+        }
+        drop(sink);
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<serde_json::Value> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 100);
+        assert_eq!(lines[42]["latency_ms"], 42);
+        assert_eq!(lines[0]["kind"], "validate-passed");
+    }
+}
@@ -0,0 +1,300 @@
+//! Hedged validate calls.
+//!
+//! A second validate call is sent when the first one is slower than a percentile of recent calls, and the first
+//! passed captcha wins. Hedges are limited to a share of validate calls and not sent while the previous call
+//! failed, so an outage never doubles the load on GeeTest.
+
+use crate::error::Error;
+use futures_util::future::{self, Either};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+const LATENCY_WINDOW: usize = 128;
+const MIN_SAMPLES: usize = 16;
+const MAX_BUDGET: f64 = 10.0;
+
+/// Hedged validate calls, see [`ClientBuilder::hedge`](crate::client::ClientBuilder::hedge).
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HedgeConfig {
+    /// Latency percentile of recent validate calls after which a second call is sent, `0.95` for p95.
+    pub percentile: f64,
+    pub min_delay_ms: u64,
+    /// Upper bound of the delay, also used until enough latencies are observed.
+    pub max_delay_ms: u64,
+    /// Hedged calls per validate call at most, with bursts of up to 10 hedges.
+    pub budget_ratio: f64,
+}
+
+impl Default for HedgeConfig {
+    fn default() -> Self {
+        Self {
+            percentile: 0.95,
+            min_delay_ms: 20,
+            max_delay_ms: 1_000,
+            budget_ratio: 0.05,
+        }
+    }
+}
+
+/// Hedging statistics of a [`Client`](crate::client::Client).
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HedgeStats {
+    /// Second calls sent.
+    pub hedges_sent: u64,
+    /// Second calls answering first with a passed captcha.
+    pub hedge_wins: u64,
+    /// Hedges skipped because the budget was used up.
+    pub budget_exhausted: u64,
+}
+
+struct State {
+    latencies_ms: VecDeque<u64>,
+    budget: f64,
+    last_failed: bool,
+}
+
+pub(crate) struct Hedger {
+    config: HedgeConfig,
+    state: Mutex<State>,
+    hedges_sent: AtomicU64,
+    hedge_wins: AtomicU64,
+    budget_exhausted: AtomicU64,
+}
+
+type ValidateResult = Result<Option<String>, Error>;
+
+impl Hedger {
+    pub(crate) fn new(config: HedgeConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(State {
+                latencies_ms: VecDeque::with_capacity(LATENCY_WINDOW),
+                budget: 1.0,
+                last_failed: false,
+            }),
+            hedges_sent: AtomicU64::new(0),
+            hedge_wins: AtomicU64::new(0),
+            budget_exhausted: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn stats(&self) -> HedgeStats {
+        HedgeStats {
+            hedges_sent: self.hedges_sent.load(Ordering::Relaxed),
+            hedge_wins: self.hedge_wins.load(Ordering::Relaxed),
+            budget_exhausted: self.budget_exhausted.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Runs `call`, and once more if it takes longer than the hedge delay, returning the first passed captcha.
+    ///
+    /// No hedge is sent while the previous call failed, so hedging doesn't add load while GeeTest is down.
+    pub(crate) async fn run<F, Fut>(&self, call: F) -> ValidateResult
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = ValidateResult>,
+    {
+        let started = Instant::now();
+        let delay = self.delay();
+        let primary = Box::pin(async {
+            let result = call().await;
+            (result, started.elapsed())
+        });
+
+        // Latency of the primary call only, a lower bound if it was abandoned for a passed hedge.
+        let (result, latency) = match delay {
+            None => primary.await,
+            Some(delay) => match future::select(primary, Box::pin(tokio::time::sleep(delay))).await {
+                Either::Left((primary, _)) => primary,
+                Either::Right((_, primary)) if !self.take_budget() => primary.await,
+                Either::Right((_, primary)) => {
+                    self.hedges_sent.fetch_add(1, Ordering::Relaxed);
+                    tracing::debug!(delay_ms = delay.as_millis() as u64, "geetest validate hedged");
+
+                    match future::select(primary, Box::pin(call())).await {
+                        Either::Left(((result, latency), _)) if passed(&result) => (result, latency),
+                        Either::Left(((result, latency), hedge)) => (self.either(result, hedge.await), latency),
+                        Either::Right((result, _)) if passed(&result) => {
+                            self.hedge_wins.fetch_add(1, Ordering::Relaxed);
+                            (result, started.elapsed())
+                        },
+                        Either::Right((result, primary)) => {
+                            let (primary, latency) = primary.await;
+                            (self.either(primary, result), latency)
+                        },
+                    }
+                },
+            },
+        };
+
+        self.record(&result, latency);
+        result
+    }
+
+    /// Prefers a passed captcha, then a rejected one over an error.
+    fn either(&self, primary: ValidateResult, hedge: ValidateResult) -> ValidateResult {
+        if passed(&hedge) {
+            self.hedge_wins.fetch_add(1, Ordering::Relaxed);
+            hedge
+        } else if primary.is_err() && hedge.is_ok() {
+            hedge
+        } else {
+            primary
+        }
+    }
+
+    fn delay(&self) -> Option<Duration> {
+        let state = self.lock();
+        if state.last_failed {
+            return None;
+        }
+
+        let max_delay_ms = self.config.max_delay_ms.max(self.config.min_delay_ms);
+        if state.latencies_ms.len() < MIN_SAMPLES {
+            return Some(Duration::from_millis(max_delay_ms));
+        }
+        let mut latencies: Vec<u64> = state.latencies_ms.iter().copied().collect();
+        latencies.sort_unstable();
+        let percentile = self.config.percentile.clamp(0.0, 1.0);
+        let index = ((latencies.len() - 1) as f64 * percentile).round() as usize;
+        Some(Duration::from_millis(
+            latencies[index].clamp(self.config.min_delay_ms, max_delay_ms),
+        ))
+    }
+
+    fn take_budget(&self) -> bool {
+        let mut state = self.lock();
+        if state.budget >= 1.0 {
+            state.budget -= 1.0;
+            true
+        } else {
+            self.budget_exhausted.fetch_add(1, Ordering::Relaxed);
+            false
+        }
+    }
+
+    fn record(&self, result: &ValidateResult, latency: Duration) {
+        let mut state = self.lock();
+        state.budget = (state.budget + self.config.budget_ratio.max(0.0)).min(MAX_BUDGET);
+        state.last_failed = result.is_err();
+        if result.is_ok() {
+            if state.latencies_ms.len() == LATENCY_WINDOW {
+                state.latencies_ms.pop_front();
+            }
+            state.latencies_ms.push_back(latency.as_millis() as u64);
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|error| error.into_inner())
+    }
+}
+
+fn passed(result: &ValidateResult) -> bool {
+    matches!(result, Ok(Some(_)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{io, sync::atomic::AtomicUsize};
+
+    fn hedger(budget_ratio: f64) -> Hedger {
+        Hedger::new(HedgeConfig {
+            percentile: 0.9,
+            min_delay_ms: 10,
+            max_delay_ms: 100,
+            budget_ratio,
+        })
+    }
+
+    fn failure() -> ValidateResult {
+        Err(io::Error::new(io::ErrorKind::ConnectionRefused, "unavailable").into())
+    }
+
+    #[test]
+    fn delay_from_latency_percentile() {
+        let hedger = hedger(0.0);
+        assert_eq!(
+            hedger.delay(),
+            Some(Duration::from_millis(100)),
+            "max delay until enough samples"
+        );
+
+        for latency_ms in 1..=MIN_SAMPLES as u64 {
+            hedger.record(&Ok(None), Duration::from_millis(latency_ms * 5));
+        }
+        // p90 of 5, 10, .., 80 ms, the 15th sample.
+        assert_eq!(hedger.delay(), Some(Duration::from_millis(75)));
+
+        for _ in 0..LATENCY_WINDOW {
+            hedger.record(&Ok(None), Duration::from_millis(1));
+        }
+        assert_eq!(hedger.delay(), Some(Duration::from_millis(10)), "clamped to min delay");
+        for _ in 0..LATENCY_WINDOW {
+            hedger.record(&Ok(None), Duration::from_secs(1));
+        }
+        assert_eq!(hedger.delay(), Some(Duration::from_millis(100)), "clamped to max delay");
+    }
+
+    #[test]
+    fn no_hedge_after_failure() {
+        let hedger = hedger(0.0);
+        hedger.record(&failure(), Duration::ZERO);
+        assert_eq!(hedger.delay(), None);
+        hedger.record(&Ok(None), Duration::ZERO);
+        assert!(hedger.delay().is_some());
+    }
+
+    #[test]
+    fn budget_exhausted() {
+        let hedger = hedger(0.5);
+        assert!(hedger.take_budget());
+        assert!(!hedger.take_budget());
+        assert_eq!(hedger.stats().budget_exhausted, 1);
+
+        hedger.record(&Ok(None), Duration::ZERO);
+        assert!(!hedger.take_budget());
+        hedger.record(&Ok(None), Duration::ZERO);
+        assert!(hedger.take_budget());
+        assert_eq!(hedger.stats().budget_exhausted, 2);
+    }
+
+    #[tokio::test]
+    async fn records_primary_latency() {
+        let hedger = hedger(1.0);
+        let calls = AtomicUsize::new(0);
+        // The primary is rejected after 30 ms, the hedge passes much later.
+        let call = || {
+            let hedge = calls.fetch_add(1, Ordering::SeqCst) > 0;
+            async move {
+                if hedge {
+                    tokio::time::sleep(Duration::from_millis(200)).await;
+                    Ok(Some("seccode".to_owned()))
+                } else {
+                    tokio::time::sleep(Duration::from_millis(30)).await;
+                    Ok(None)
+                }
+            }
+        };
+
+        for _ in 0..MIN_SAMPLES {
+            hedger.record(&Ok(None), Duration::from_millis(10));
+        }
+        assert_eq!(hedger.run(call).await.unwrap().as_deref(), Some("seccode"));
+        assert_eq!(hedger.stats().hedges_sent, 1);
+        assert_eq!(hedger.stats().hedge_wins, 1);
+
+        let recorded = *hedger.lock().latencies_ms.back().unwrap();
+        assert!((30..200).contains(&recorded), "{} ms", recorded);
+    }
+}
@@ -1,11 +1,48 @@
+#[cfg(feature = "assets")]
+pub mod assets;
+pub mod backend;
+pub mod breaker;
+#[cfg(feature = "runtime")]
 pub mod client;
+#[cfg(feature = "runtime")]
+pub mod config;
+#[cfg(feature = "runtime")]
+pub mod connector;
+pub mod digest;
 pub mod error;
+pub mod events;
+#[cfg(feature = "runtime")]
+pub mod hedge;
+#[cfg(feature = "metrics")]
+pub mod metrics;
 pub mod models;
+pub mod protocol;
+#[cfg(feature = "runtime")]
+pub mod proxy;
+pub mod ratelimit;
+#[cfg(feature = "runtime")]
+pub mod resolver;
+pub mod secret;
 pub mod server;
+#[cfg(feature = "session-binding")]
+pub mod session;
+mod singleflight;
+#[cfg(feature = "pass-token")]
+pub mod token;
 
+#[cfg(feature = "metrics")]
+pub use crate::metrics::Metrics;
 pub use crate::{
-    client::Client,
+    backend::CaptchaBackend,
+    digest::ChallengeDigest,
     error::Error,
     models::{ClientType, DigestMod, UserInfo},
-    server::{Handler, Server},
+    secret::CaptchaSecret,
+    server::{Handler, HandlerBuilder},
+};
+#[cfg(feature = "runtime")]
+pub use crate::{
+    client::{Client, ClientBuilder},
+    config::GeetestConfig,
+    server::Server,
 };
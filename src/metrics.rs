@@ -0,0 +1,94 @@
+//! Prometheus metrics: handled requests by operation and outcome (`geetest_requests_total`), GeeTest API latency
+//! (`geetest_api_request_duration_seconds`) and the last bypass status (`geetest_bypass_status`).
+//!
+//! [`Server`](crate::server::Server) exposes them on `GET /metrics` with `metrics_route(true)`.
+
+use crate::models::{Endpoint, Operation, Outcome};
+use prometheus::{
+    exponential_buckets, Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+use std::time::Duration;
+
+pub static TEXT_FORMAT: &str = "text/plain; version=0.0.4";
+
+/// Prometheus metrics for captcha traffic.
+///
+/// * `geetest_requests_total{operation, outcome}` — handled register and validate calls,
+/// * `geetest_api_request_duration_seconds{endpoint}` — GeeTest API latency,
+/// * `geetest_bypass_status` — last known bypass status (1 if GeeTest is online).
+///
+/// Cheap to clone, all clones share the same collectors.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    api_latency: HistogramVec,
+    bypass_status: IntGauge,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::with_registry(Registry::new()).expect("Metrics are registered in a fresh registry")
+    }
+
+    /// Registers collectors in an existing registry, e.g. the one already exported by the service.
+    pub fn with_registry(registry: Registry) -> Result<Self, prometheus::Error> {
+        let requests = IntCounterVec::new(
+            Opts::new(
+                "geetest_requests_total",
+                "Handled captcha requests by operation and outcome",
+            ),
+            &["operation", "outcome"],
+        )?;
+        let api_latency = HistogramVec::new(
+            HistogramOpts::new("geetest_api_request_duration_seconds", "GeeTest API request latency")
+                .buckets(exponential_buckets(0.005, 2.0, 12)?),
+            &["endpoint"],
+        )?;
+        let bypass_status = IntGauge::new("geetest_bypass_status", "Last known GeeTest bypass status")?;
+
+        registry.register(Box::new(requests.clone()))?;
+        registry.register(Box::new(api_latency.clone()))?;
+        registry.register(Box::new(bypass_status.clone()))?;
+
+        Ok(Self {
+            registry,
+            requests,
+            api_latency,
+            bypass_status,
+        })
+    }
+
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    pub fn record(&self, operation: Operation, outcome: Outcome) {
+        self.requests
+            .with_label_values(&[operation.as_str(), outcome.as_str()])
+            .inc();
+    }
+
+    pub fn observe_api_latency(&self, endpoint: Endpoint, latency: Duration) {
+        self.api_latency
+            .with_label_values(&[endpoint.as_str()])
+            .observe(latency.as_secs_f64());
+    }
+
+    pub fn set_bypass_status(&self, status: bool) {
+        self.bypass_status.set(status as i64);
+    }
+
+    /// Encodes all metrics of the registry in Prometheus text format.
+    pub fn encode(&self) -> Result<Vec<u8>, prometheus::Error> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(buffer)
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
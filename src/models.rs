@@ -1,12 +1,21 @@
-use crate::client::SDK;
+//! Request and response models of the captcha server and the GeeTest API.
+//!
+//! GeeTest responses are parsed leniently where GeeTest has used several encodings, and strictly otherwise:
+//!
+//! - unknown fields are ignored;
+//! - `status` accepts `"success"`/`"fail"`, `"true"`/`"false"` in any case, booleans and `0`/`1`;
+//! - `success` accepts `0`/`1`, `"0"`/`"1"` and booleans, other numbers are rejected;
+//! - a `seccode` of `"false"`, `false` or `null` is a rejected captcha, an empty seccode is an error.
+
+use crate::{breaker::CircuitState, error::ParseError, protocol::SDK};
 use serde_derive::{Deserialize, Serialize};
-use std::net::IpAddr;
+use std::{fmt, net::IpAddr, str::FromStr};
 
-#[derive(Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
+#[derive(Clone, PartialEq, Default, Serialize, Deserialize)]
 pub struct UserInfo {
     pub user_id: Option<String>,
     pub client_type: Option<ClientType>,
-    #[serde(with = "maybe_ipaddr_as_string")]
+    #[serde(default, with = "maybe_ipaddr_as_string")]
     pub ip_address: Option<IpAddr>,
 }
 
@@ -31,6 +40,16 @@ impl UserInfo {
     }
 }
 
+impl fmt::Debug for UserInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UserInfo")
+            .field("user_id", &self.user_id)
+            .field("client_type", &self.client_type)
+            .field("ip_address", &self.ip_address.map(|_| format_args!("<redacted>")))
+            .finish()
+    }
+}
+
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct StatusRequest {
     #[serde(rename = "gt")]
@@ -51,6 +70,9 @@ pub struct ClientRegisterResponse {
     pub challenge: String,
     #[serde(rename = "gt")]
     pub captcha_id: String,
+    /// Proof that the challenge was issued to a session, see [`crate::session::SessionBinder`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_binding: Option<String>,
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
@@ -59,6 +81,8 @@ pub struct ClientValidateResponse {
     pub result: bool,
     pub version: String,
     pub msg: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pass_token: Option<String>,
 }
 
 impl ClientValidateResponse {
@@ -67,6 +91,7 @@ impl ClientValidateResponse {
             result: true,
             version: SDK.to_owned(),
             msg: None,
+            pass_token: None,
         }
     }
 
@@ -75,11 +100,17 @@ impl ClientValidateResponse {
             result: false,
             version: SDK.to_owned(),
             msg: Some(message.into()),
+            pass_token: None,
         }
     }
+
+    pub fn pass_token(mut self, token: impl Into<String>) -> Self {
+        self.pass_token = Some(token.into());
+        self
+    }
 }
 
-#[derive(Deserialize, Serialize, Clone, Debug)]
+#[derive(Deserialize, Serialize, Clone)]
 pub struct ClientValidateRequest {
     #[serde(rename = "geetest_challenge")]
     pub challenge: String,
@@ -87,6 +118,20 @@ pub struct ClientValidateRequest {
     pub validate: String,
     #[serde(rename = "geetest_seccode")]
     pub seccode: String,
+    /// `session_binding` returned by register for this challenge.
+    #[serde(rename = "geetest_session_binding", default, skip_serializing_if = "Option::is_none")]
+    pub session_binding: Option<String>,
+}
+
+impl fmt::Debug for ClientValidateRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClientValidateRequest")
+            .field("challenge", &self.challenge)
+            .field("validate", &format_args!("<redacted>"))
+            .field("seccode", &format_args!("<redacted>"))
+            .field("session_binding", &self.session_binding)
+            .finish()
+    }
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
@@ -105,11 +150,17 @@ pub struct ServerRegisterResponse {
     pub challenge: String,
 }
 
-#[derive(Deserialize, Serialize, Clone, Debug)]
+impl ServerRegisterResponse {
+    /// `None` if the challenge is empty or `"0"`, which official SDKs treat as a register failure and fall back.
+    pub fn origin_challenge(&self) -> Option<&str> {
+        Some(self.challenge.trim()).filter(|challenge| !challenge.is_empty() && *challenge != "0")
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone)]
 pub struct ServerValidateRequest {
     #[serde(flatten)]
     pub user_info: UserInfo,
-    pub digestmod: DigestMod,
     pub json_format: u32,
     pub sdk: String,
     #[serde(rename = "captchaid")]
@@ -118,20 +169,31 @@ pub struct ServerValidateRequest {
     pub challenge: String,
 }
 
+impl fmt::Debug for ServerValidateRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServerValidateRequest")
+            .field("user_info", &self.user_info)
+            .field("json_format", &self.json_format)
+            .field("sdk", &self.sdk)
+            .field("captcha_id", &self.captcha_id)
+            .field("seccode", &format_args!("<redacted>"))
+            .field("challenge", &self.challenge)
+            .finish()
+    }
+}
+
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct ServerValidateResponse {
     #[serde(with = "maybe_seccode")]
     pub seccode: Option<String>,
 }
 
-#[derive(Deserialize, Serialize, Clone, Copy, Debug)]
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum DigestMod {
-    #[cfg(feature = "digest-md5")]
+    #[default]
     Md5,
-    #[cfg(feature = "digest-sha256")]
     Sha256,
-    #[cfg(feature = "digest-hmac-sha256")]
     #[serde(rename = "hmac-sha256")]
     HmacSha256,
 }
@@ -146,49 +208,359 @@ pub enum ClientType {
     Unknown,
 }
 
+impl DigestMod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DigestMod::Md5 => "md5",
+            DigestMod::Sha256 => "sha256",
+            DigestMod::HmacSha256 => "hmac-sha256",
+        }
+    }
+}
+
+impl fmt::Display for DigestMod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for DigestMod {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &*s.trim().to_ascii_lowercase() {
+            "md5" => Ok(DigestMod::Md5),
+            "sha256" => Ok(DigestMod::Sha256),
+            "hmac-sha256" | "hmac_sha256" => Ok(DigestMod::HmacSha256),
+            _ => Err(ParseError {
+                kind: "digest mod",
+                value: s.to_owned(),
+            }),
+        }
+    }
+}
+
+impl ClientType {
+    /// Guesses client type from `User-Agent` header value.
+    ///
+    /// Mobile browsers are reported as `Mobile` (h5), HTTP libraries used by mobile app SDKs as `Native`,
+    /// other browsers as `Web`, anything else as `Unknown`.
+    pub fn from_user_agent(user_agent: &str) -> Self {
+        const NATIVE_MARKERS: &[&str] = &["okhttp", "cfnetwork", "dalvik", "alamofire", "darwin/", "geetest"];
+        const MOBILE_MARKERS: &[&str] = &[
+            "mobi",
+            "android",
+            "iphone",
+            "ipod",
+            "ipad",
+            "windows phone",
+            "opera mini",
+            "blackberry",
+        ];
+        const BROWSER_MARKERS: &[&str] = &["mozilla/", "opera/"];
+
+        let user_agent = user_agent.to_ascii_lowercase();
+        let contains_any = |markers: &[&str]| markers.iter().any(|marker| user_agent.contains(marker));
+
+        if contains_any(BROWSER_MARKERS) {
+            if contains_any(MOBILE_MARKERS) {
+                ClientType::Mobile
+            } else {
+                ClientType::Web
+            }
+        } else if contains_any(NATIVE_MARKERS) || contains_any(MOBILE_MARKERS) {
+            ClientType::Native
+        } else {
+            ClientType::Unknown
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ClientType::Web => "web",
+            ClientType::Mobile => "h5",
+            ClientType::Native => "native",
+            ClientType::Unknown => "unknown",
+        }
+    }
+}
+
+impl fmt::Display for ClientType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ClientType {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &*s.trim().to_ascii_lowercase() {
+            "web" => Ok(ClientType::Web),
+            "h5" | "mobile" => Ok(ClientType::Mobile),
+            "native" => Ok(ClientType::Native),
+            "unknown" => Ok(ClientType::Unknown),
+            _ => Err(ParseError {
+                kind: "client type",
+                value: s.to_owned(),
+            }),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Endpoint {
+    Status,
+    Register,
+    Validate,
+}
+
+impl Endpoint {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Endpoint::Status => "status",
+            Endpoint::Register => "register",
+            Endpoint::Validate => "validate",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    Register,
+    Validate,
+}
+
+impl Operation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Operation::Register => "register",
+            Operation::Validate => "validate",
+        }
+    }
+}
+
+/// Result of a handled register or validate call.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    /// Online challenge issued or captcha passed.
+    Success,
+    /// Captcha submission rejected.
+    Rejected,
+    /// GeeTest is bypassed, fail-back challenge issued or accepted.
+    Failback,
+    /// Request rejected by rate limiter.
+    RateLimited,
+    /// GeeTest API or internal error.
+    Error,
+}
+
+impl Outcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Success => "success",
+            Outcome::Rejected => "rejected",
+            Outcome::Failback => "failback",
+            Outcome::RateLimited => "ratelimited",
+            Outcome::Error => "error",
+        }
+    }
+}
+
+/// Result of the last GeeTest bypass status check.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum BypassState {
+    /// Status was not checked yet.
+    Unknown,
+    /// GeeTest is online.
+    Online,
+    /// GeeTest asked to use fail-back mode.
+    Bypassed,
+    /// Status check failed.
+    Error,
+}
+
+/// Readiness of [`Handler`](crate::server::Handler) to serve captcha requests.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Readiness {
+    pub ready: bool,
+    /// Fail-back challenges are currently issued instead of GeeTest ones.
+    pub failback: bool,
+    pub bypass_status: BypassState,
+    pub bypass_checked_at_ms: Option<u64>,
+    pub circuit_state: Option<CircuitState>,
+}
+
+/// `0`/`1`, also accepting booleans and `"0"`/`"1"` strings. Other numbers are rejected.
 mod bool_as_u8 {
-    use serde::{Deserialize, Deserializer, Serializer};
+    use serde::{
+        de::{self, Unexpected, Visitor},
+        Deserializer, Serializer,
+    };
+    use std::fmt;
 
     pub(crate) fn serialize<S: Serializer>(value: &bool, s: S) -> Result<S::Ok, S::Error> {
         s.serialize_u8(*value as u8)
     }
 
     pub(crate) fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<bool, D::Error> {
-        u8::deserialize(d).map(|value| if value == 0 { false } else { true })
+        d.deserialize_any(FlagVisitor)
+    }
+
+    struct FlagVisitor;
+
+    impl<'de> Visitor<'de> for FlagVisitor {
+        type Value = bool;
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("0, 1 or a boolean")
+        }
+
+        fn visit_bool<E: de::Error>(self, value: bool) -> Result<bool, E> {
+            Ok(value)
+        }
+
+        fn visit_u64<E: de::Error>(self, value: u64) -> Result<bool, E> {
+            match value {
+                0 => Ok(false),
+                1 => Ok(true),
+                _ => Err(E::invalid_value(Unexpected::Unsigned(value), &self)),
+            }
+        }
+
+        fn visit_i64<E: de::Error>(self, value: i64) -> Result<bool, E> {
+            if value >= 0 {
+                self.visit_u64(value as u64)
+            } else {
+                Err(E::invalid_value(Unexpected::Signed(value), &self))
+            }
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<bool, E> {
+            match value.trim() {
+                "0" => Ok(false),
+                "1" => Ok(true),
+                _ => Err(E::invalid_value(Unexpected::Str(value), &self)),
+            }
+        }
     }
 }
 
+/// `"success"`/`"fail"`, also accepting `"true"`/`"false"` in any case, booleans and `0`/`1`.
 mod bool_as_string {
-    use serde::{de::Unexpected, Deserialize, Deserializer, Serializer};
+    use serde::{
+        de::{self, Unexpected, Visitor},
+        Deserializer, Serializer,
+    };
+    use std::fmt;
 
     pub(crate) fn serialize<S: Serializer>(value: &bool, s: S) -> Result<S::Ok, S::Error> {
         s.serialize_str(if *value { "success" } else { "fail" })
     }
 
     pub(crate) fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<bool, D::Error> {
-        String::deserialize(d).and_then(|value| match &*value {
-            "success" => Ok(true),
-            "fail" => Ok(false),
-            unexpected => Err(serde::de::Error::invalid_value(
-                Unexpected::Str(unexpected),
-                &"fail or success",
-            )),
-        })
+        d.deserialize_any(StatusVisitor)
+    }
+
+    struct StatusVisitor;
+
+    impl<'de> Visitor<'de> for StatusVisitor {
+        type Value = bool;
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("fail or success")
+        }
+
+        fn visit_bool<E: de::Error>(self, value: bool) -> Result<bool, E> {
+            Ok(value)
+        }
+
+        fn visit_u64<E: de::Error>(self, value: u64) -> Result<bool, E> {
+            match value {
+                0 => Ok(false),
+                1 => Ok(true),
+                _ => Err(E::invalid_value(Unexpected::Unsigned(value), &self)),
+            }
+        }
+
+        fn visit_i64<E: de::Error>(self, value: i64) -> Result<bool, E> {
+            if value >= 0 {
+                self.visit_u64(value as u64)
+            } else {
+                Err(E::invalid_value(Unexpected::Signed(value), &self))
+            }
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<bool, E> {
+            match &*value.trim().to_ascii_lowercase() {
+                "success" | "true" => Ok(true),
+                "fail" | "false" => Ok(false),
+                _ => Err(E::invalid_value(Unexpected::Str(value), &self)),
+            }
+        }
     }
 }
 
+/// Seccode, or `"false"` (in any case), `false` or `null` for a rejected captcha. Empty seccodes are rejected.
 mod maybe_seccode {
-    use serde::{Deserialize, Deserializer, Serializer};
+    use serde::{
+        de::{self, Unexpected, Visitor},
+        Deserializer, Serializer,
+    };
+    use std::fmt;
 
     pub(crate) fn serialize<S: Serializer>(value: &Option<String>, s: S) -> Result<S::Ok, S::Error> {
         match value {
             None => s.serialize_str("false"),
-            Some(value) => s.serialize_str(&**value),
+            Some(value) => s.serialize_str(value),
         }
     }
 
     pub(crate) fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<String>, D::Error> {
-        String::deserialize(d).map(|value| if value == "false" { None } else { Some(value) })
+        d.deserialize_any(SeccodeVisitor)
+    }
+
+    struct SeccodeVisitor;
+
+    impl<'de> Visitor<'de> for SeccodeVisitor {
+        type Value = Option<String>;
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("seccode or false")
+        }
+
+        fn visit_bool<E: de::Error>(self, value: bool) -> Result<Self::Value, E> {
+            match value {
+                false => Ok(None),
+                true => Err(E::invalid_value(Unexpected::Bool(value), &self)),
+            }
+        }
+
+        fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+            Ok(None)
+        }
+
+        fn visit_none<E: de::Error>(self) -> Result<Self::Value, E> {
+            Ok(None)
+        }
+
+        fn visit_some<D: Deserializer<'de>>(self, d: D) -> Result<Self::Value, D::Error> {
+            d.deserialize_any(self)
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+            if value.trim().is_empty() {
+                Err(E::invalid_value(Unexpected::Str(value), &self))
+            } else if value.trim().eq_ignore_ascii_case("false") {
+                Ok(None)
+            } else {
+                Ok(Some(value.to_owned()))
+            }
+        }
     }
 }
 
@@ -0,0 +1,502 @@
+//! Sans-IO GeeTest protocol.
+//!
+//! Builds GeeTest API requests and captcha server replies as plain [`http`] values and decodes responses, leaving
+//! I/O to the caller. [`Client`] and [`Server`] are adapters over hyper and tokio, enabled by the default `runtime`
+//! feature. Other runtimes can send [`Protocol`] requests with any HTTP client:
+//!
+//! ```
+//! use geetest::{
+//!     protocol::{self, Endpoints, Protocol},
+//!     CaptchaSecret, DigestMod, Error, UserInfo,
+//! };
+//!
+//! /// Stands in for an HTTP client sending the request and returning the response body.
+//! fn send(request: http::Request<Vec<u8>>) -> Result<Vec<u8>, Error> {
+//!     assert_eq!(request.uri().path(), "/register.php");
+//!     Ok(br#"{"challenge": "0f6c0d4bd1d3a2bc6f1b0e5bc3c93d1e"}"#.to_vec())
+//! }
+//!
+//! # fn main() -> Result<(), Error> {
+//! let protocol = Protocol::new("geetest-captcha-id", DigestMod::Md5, Endpoints::default());
+//! let secret = CaptchaSecret::from("geetest-captcha-secret");
+//! let digest = |origin: &str, _: &CaptchaSecret| origin.to_owned();
+//!
+//! let body = send(protocol.register_request(UserInfo::new())?)?;
+//! let reply = match Protocol::register_response(&body)? {
+//!     Some(origin_challenge) => {
+//!         protocol::online_register_reply(protocol.captcha_id(), &origin_challenge, &digest, &secret)
+//!     },
+//!     None => protocol::failback_register_reply(protocol.captcha_id()),
+//! };
+//! let response = protocol::json_reply(&reply)?;
+//! assert_eq!(response.status(), http::StatusCode::OK);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`Client`]: crate::client::Client
+//! [`Server`]: crate::server::Server
+
+use crate::{
+    digest::ChallengeDigest,
+    error::Error,
+    models::{
+        ClientRegisterResponse, ClientType, ClientValidateRequest, ClientValidateResponse, DigestMod,
+        ServerRegisterRequest, ServerRegisterResponse, ServerValidateRequest, ServerValidateResponse, StatusRequest,
+        StatusResponse, UserInfo,
+    },
+    secret::CaptchaSecret,
+};
+use http::{
+    header::{self, HeaderMap},
+    Method, Request, Response, StatusCode,
+};
+use rand::seq::SliceRandom;
+use serde::Serialize;
+use serde_derive::{Deserialize, Serialize};
+use std::net::IpAddr;
+
+pub static GEETEST_REGISTER_URL: &str = "https://api.geetest.com/register.php";
+pub static GEETEST_VALIDATE_URL: &str = "https://api.geetest.com/validate.php";
+pub static GEETEST_STATUS_URL: &str = "https://bypass.geetest.com/v1/bypass_status.php";
+
+pub static SDK: &str = "geetest rust sdk 1.0";
+
+pub static FORWARDED_FOR_HEADER: &str = "x-forwarded-for";
+
+/// `Retry-After` is capped at a day.
+const MAX_RETRY_AFTER_SECS: u64 = 24 * 60 * 60;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Endpoints {
+    pub register: String,
+    pub validate: String,
+    pub status: String,
+}
+
+impl Default for Endpoints {
+    fn default() -> Self {
+        Self {
+            register: GEETEST_REGISTER_URL.to_owned(),
+            validate: GEETEST_VALIDATE_URL.to_owned(),
+            status: GEETEST_STATUS_URL.to_owned(),
+        }
+    }
+}
+
+/// GeeTest API calls of a captcha.
+#[derive(Clone, Debug)]
+pub struct Protocol {
+    captcha_id: String,
+    digestmod: DigestMod,
+    endpoints: Endpoints,
+}
+
+impl Protocol {
+    pub fn new(captcha_id: impl Into<String>, digestmod: DigestMod, endpoints: Endpoints) -> Self {
+        Self {
+            captcha_id: captcha_id.into(),
+            digestmod,
+            endpoints,
+        }
+    }
+
+    pub fn captcha_id(&self) -> &str {
+        &self.captcha_id
+    }
+
+    pub fn digestmod(&self) -> DigestMod {
+        self.digestmod
+    }
+
+    pub fn endpoints(&self) -> &Endpoints {
+        &self.endpoints
+    }
+
+    pub fn status_request(&self) -> Result<Request<Vec<u8>>, Error> {
+        let request = StatusRequest {
+            captcha_id: self.captcha_id.clone(),
+        };
+        let url = format!("{}?{}", self.endpoints.status, serde_qs::to_string(&request)?);
+        Ok(Request::get(url).body(Vec::new())?)
+    }
+
+    pub fn register_request(&self, user_info: UserInfo) -> Result<Request<Vec<u8>>, Error> {
+        let request = ServerRegisterRequest {
+            user_info,
+            digestmod: self.digestmod,
+            json_format: 1,
+            sdk: SDK.to_owned(),
+            captcha_id: self.captcha_id.clone(),
+        };
+        let url = format!("{}?{}", self.endpoints.register, serde_qs::to_string(&request)?);
+        Ok(Request::get(url).body(Vec::new())?)
+    }
+
+    pub fn validate_request(
+        &self,
+        seccode: String,
+        challenge: String,
+        user_info: UserInfo,
+    ) -> Result<Request<Vec<u8>>, Error> {
+        let body = ServerValidateRequest {
+            user_info,
+            json_format: 1,
+            sdk: SDK.to_owned(),
+            captcha_id: self.captcha_id.clone(),
+            seccode,
+            challenge,
+        };
+        Ok(Request::builder()
+            .method(Method::POST)
+            .uri(&self.endpoints.validate)
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(serde_qs::to_string(&body)?.into_bytes())?)
+    }
+
+    /// Returns `false` if GeeTest asks to use fail-back mode.
+    pub fn status_response(body: &[u8]) -> Result<bool, Error> {
+        Ok(serde_json::from_slice::<StatusResponse>(body)?.status)
+    }
+
+    /// Returns the origin challenge, `None` if GeeTest asks for fail-back mode.
+    pub fn register_response(body: &[u8]) -> Result<Option<String>, Error> {
+        let response = serde_json::from_slice::<ServerRegisterResponse>(body)?;
+        Ok(response.origin_challenge().map(ToOwned::to_owned))
+    }
+
+    /// Returns `None` if the captcha was not passed.
+    pub fn validate_response(body: &[u8]) -> Result<Option<String>, Error> {
+        Ok(serde_json::from_slice::<ServerValidateResponse>(body)?.seccode)
+    }
+}
+
+/// Register reply with the challenge derived from the origin challenge returned by GeeTest.
+pub fn online_register_reply(
+    captcha_id: &str,
+    origin_challenge: &str,
+    digest: &dyn ChallengeDigest,
+    secret: &CaptchaSecret,
+) -> ClientRegisterResponse {
+    ClientRegisterResponse {
+        success: true,
+        new_captcha: true,
+        challenge: digest.digest(origin_challenge, secret),
+        captcha_id: captcha_id.to_owned(),
+        session_binding: None,
+    }
+}
+
+/// Register reply with a random local challenge, used while GeeTest is bypassed.
+pub fn failback_register_reply(captcha_id: &str) -> ClientRegisterResponse {
+    let challenge = "abcdefghijklmnopqrstuvwxyz0123456789"
+        .as_bytes()
+        .choose_multiple(&mut rand::thread_rng(), 32)
+        .copied()
+        .map(|b| b as char)
+        .collect();
+    ClientRegisterResponse {
+        success: false,
+        captcha_id: captcha_id.to_owned(),
+        new_captcha: true,
+        challenge,
+        session_binding: None,
+    }
+}
+
+/// Rejection reply for a validate request with a missing field, `None` if all are present.
+pub fn check_validate_request(request: &ClientValidateRequest) -> Option<ClientValidateResponse> {
+    let missing =
+        request.challenge.trim().is_empty() || request.validate.trim().is_empty() || request.seccode.trim().is_empty();
+    missing.then(|| ClientValidateResponse::error("Invalid request fields"))
+}
+
+/// Validate reply for a passed captcha, either approved by GeeTest or accepted in fail-back mode.
+pub fn passed_reply() -> ClientValidateResponse {
+    ClientValidateResponse::success()
+}
+
+/// Validate reply for a seccode GeeTest did not approve.
+pub fn rejected_reply() -> ClientValidateResponse {
+    ClientValidateResponse::error("Invalid security code")
+}
+
+/// Form body of `POST /validate`.
+pub fn parse_validate_body(body: &[u8]) -> Result<ClientValidateRequest, Error> {
+    serde_qs::from_bytes(body).map_err(Into::into)
+}
+
+/// Client type from `User-Agent`, and client IP address from `X-Forwarded-For` or the peer address.
+///
+/// Each of `trusted_proxies` reverse proxies appends the address it got the request from to `X-Forwarded-For`, so
+/// the client address is the `trusted_proxies`-th entry from the right. Entries to the left of it are set by the
+/// client and are ignored. With no trusted proxies the header is not used.
+pub fn user_info(headers: &HeaderMap, peer_ip: Option<IpAddr>, trusted_proxies: usize) -> UserInfo {
+    let client_type = headers
+        .get(header::USER_AGENT)
+        .and_then(|user_agent| user_agent.to_str().ok())
+        .map_or(ClientType::Unknown, ClientType::from_user_agent);
+    let user_info = UserInfo::new().client_type(client_type);
+
+    let forwarded_for = || {
+        headers
+            .get_all(FORWARDED_FOR_HEADER)
+            .iter()
+            .map(|value| value.to_str().ok())
+            .collect::<Option<Vec<_>>>()?
+            .join(",")
+            .rsplit(',')
+            .nth(trusted_proxies.checked_sub(1)?)?
+            .trim()
+            .parse()
+            .ok()
+    };
+    match forwarded_for().or(peer_ip) {
+        Some(ip_addr) => user_info.ip_address(ip_addr),
+        None => user_info,
+    }
+}
+
+pub fn json_reply<T: Serialize>(reply: &T) -> Result<Response<Vec<u8>>, Error> {
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(serde_json::to_vec(reply)?)?)
+}
+
+/// Maps an error to a JSON reply: `400` for malformed requests, `429` with `Retry-After` when rate limited, `500`
+/// otherwise.
+pub fn error_reply(error: &Error) -> Result<Response<Vec<u8>>, Error> {
+    let error_body = ClientValidateResponse::error(error.to_string());
+
+    let status_code = match error.unshared() {
+        Error::Query(_) | Error::Json(_) => StatusCode::BAD_REQUEST,
+        Error::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+
+    let mut response = Response::builder()
+        .status(status_code)
+        .header(header::CONTENT_TYPE, "application/json");
+    if let Error::RateLimited(retry_after) = error.unshared() {
+        let seconds = retry_after
+            .as_secs()
+            .saturating_add((retry_after.subsec_nanos() > 0) as u64);
+        response = response.header(header::RETRY_AFTER, seconds.clamp(1, MAX_RETRY_AFTER_SECS));
+    }
+
+    Ok(response.body(serde_json::to_vec(&error_body)?)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::header::HeaderValue;
+    use std::{sync::Arc, time::Duration};
+
+    fn protocol() -> Protocol {
+        let endpoints = Endpoints {
+            register: "http://127.0.0.1/register.php".to_owned(),
+            ..Endpoints::default()
+        };
+        Protocol::new("captcha-id", DigestMod::Sha256, endpoints)
+    }
+
+    #[test]
+    fn status_request() {
+        let request = protocol().status_request().unwrap();
+        assert_eq!(request.method(), Method::GET);
+        assert_eq!(
+            request.uri(),
+            "https://bypass.geetest.com/v1/bypass_status.php?gt=captcha-id"
+        );
+        assert!(request.body().is_empty());
+    }
+
+    #[test]
+    fn register_request() {
+        let user_info = UserInfo::new().user_id("user").ip_address([203, 0, 113, 7].into());
+        let request = protocol().register_request(user_info).unwrap();
+        assert_eq!(request.method(), Method::GET);
+        assert_eq!(
+            request.uri(),
+            "http://127.0.0.1/register.php?user_id=user&ip_address=203.0.113.7&digestmod=sha256&json_format=1&\
+             sdk=geetest+rust+sdk+1.0&gt=captcha-id"
+        );
+        assert!(request.body().is_empty());
+    }
+
+    #[test]
+    fn validate_request() {
+        let request = protocol()
+            .validate_request("seccode".to_owned(), "challenge".to_owned(), UserInfo::new())
+            .unwrap();
+        assert_eq!(request.method(), Method::POST);
+        assert_eq!(request.uri(), GEETEST_VALIDATE_URL);
+        assert_eq!(
+            request.headers()[header::CONTENT_TYPE],
+            "application/x-www-form-urlencoded"
+        );
+        assert_eq!(
+            String::from_utf8(request.into_body()).unwrap(),
+            "json_format=1&sdk=geetest+rust+sdk+1.0&captchaid=captcha-id&seccode=seccode&challenge=challenge"
+        );
+    }
+
+    #[test]
+    fn invalid_endpoint() {
+        let endpoints = Endpoints {
+            validate: "not a url".to_owned(),
+            ..Endpoints::default()
+        };
+        let protocol = Protocol::new("captcha-id", DigestMod::Md5, endpoints);
+        let result = protocol.validate_request("seccode".to_owned(), "challenge".to_owned(), UserInfo::new());
+        assert!(matches!(result, Err(Error::Http(_))));
+    }
+
+    #[test]
+    fn responses() {
+        assert!(Protocol::status_response(br#"{"status": "success"}"#).unwrap());
+        assert!(!Protocol::status_response(br#"{"status": "fail"}"#).unwrap());
+        assert!(matches!(Protocol::status_response(b"<html>"), Err(Error::Json(_))));
+
+        assert_eq!(
+            Protocol::register_response(br#"{"challenge": "origin"}"#)
+                .unwrap()
+                .as_deref(),
+            Some("origin")
+        );
+        assert_eq!(Protocol::register_response(br#"{"challenge": "0"}"#).unwrap(), None);
+        assert_eq!(Protocol::register_response(br#"{"challenge": ""}"#).unwrap(), None);
+
+        assert_eq!(
+            Protocol::validate_response(br#"{"seccode": "seccode"}"#)
+                .unwrap()
+                .as_deref(),
+            Some("seccode")
+        );
+        assert_eq!(Protocol::validate_response(br#"{"seccode": "false"}"#).unwrap(), None);
+    }
+
+    #[test]
+    fn register_replies() {
+        let digest = |origin: &str, secret: &CaptchaSecret| format!("{}:{}", origin, secret.expose_secret());
+        let reply = online_register_reply("captcha-id", "origin", &digest, &CaptchaSecret::from("secret"));
+        assert!(reply.success);
+        assert!(reply.new_captcha);
+        assert_eq!(reply.challenge, "origin:secret");
+        assert_eq!(reply.captcha_id, "captcha-id");
+
+        let reply = failback_register_reply("captcha-id");
+        assert!(!reply.success);
+        assert_eq!(reply.captcha_id, "captcha-id");
+        assert_eq!(reply.challenge.len(), 32);
+        assert!(reply
+            .challenge
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit()));
+        assert_ne!(failback_register_reply("captcha-id").challenge, reply.challenge);
+    }
+
+    #[test]
+    fn validate_replies() {
+        let request = parse_validate_body(b"geetest_challenge=c&geetest_validate=v&geetest_seccode=s").unwrap();
+        assert!(check_validate_request(&request).is_none());
+
+        let request = parse_validate_body(b"geetest_challenge=c&geetest_validate=+&geetest_seccode=s").unwrap();
+        let reply = check_validate_request(&request).unwrap();
+        assert!(!reply.result);
+        assert_eq!(reply.msg.as_deref(), Some("Invalid request fields"));
+        assert!(matches!(
+            parse_validate_body(b"geetest_challenge=c"),
+            Err(Error::Query(_))
+        ));
+
+        assert!(passed_reply().result);
+        assert_eq!(passed_reply().msg, None);
+        assert!(!rejected_reply().result);
+        assert_eq!(rejected_reply().msg.as_deref(), Some("Invalid security code"));
+    }
+
+    #[test]
+    fn json_replies() {
+        let response = json_reply(&passed_reply()).unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["result"], "success");
+    }
+
+    #[test]
+    fn error_status() {
+        let status = |error: Error| error_reply(&error).unwrap().status();
+        let query_error = || parse_validate_body(b"").unwrap_err();
+
+        assert_eq!(status(query_error()), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            status(Error::Json(serde_json::from_slice::<()>(b"").unwrap_err())),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(status(Error::Shared(Arc::new(query_error()))), StatusCode::BAD_REQUEST);
+        assert_eq!(status(Error::CircuitOpen), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(
+            status(Error::Timeout(Duration::from_secs(1))),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+
+        let response = error_reply(&Error::CircuitOpen).unwrap();
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["result"], "fail");
+        assert_eq!(body["msg"], Error::CircuitOpen.to_string());
+    }
+
+    fn client_ip(forwarded_for: &[&str], trusted_proxies: usize) -> Option<IpAddr> {
+        let mut headers = HeaderMap::new();
+        for value in forwarded_for {
+            headers.append(FORWARDED_FOR_HEADER, HeaderValue::from_str(value).unwrap());
+        }
+        user_info(&headers, Some([10, 0, 0, 1].into()), trusted_proxies).ip_address
+    }
+
+    #[test]
+    fn forwarded_for_trusted_hops() {
+        let ip = |ip: &str| Some(ip.parse::<IpAddr>().unwrap());
+        let spoofed = ["9.9.9.9, 198.51.100.1"];
+
+        assert_eq!(client_ip(&spoofed, 0), ip("10.0.0.1"));
+        assert_eq!(client_ip(&spoofed, 1), ip("198.51.100.1"));
+        assert_eq!(client_ip(&spoofed, 2), ip("9.9.9.9"));
+        assert_eq!(client_ip(&spoofed, 3), ip("10.0.0.1"));
+
+        assert_eq!(
+            client_ip(&["9.9.9.9", "198.51.100.1 , 2001:db8::1"], 1),
+            ip("2001:db8::1")
+        );
+        assert_eq!(
+            client_ip(&["9.9.9.9", "198.51.100.1 , 2001:db8::1"], 2),
+            ip("198.51.100.1")
+        );
+        assert_eq!(client_ip(&["9.9.9.9, unknown"], 1), ip("10.0.0.1"));
+        assert_eq!(client_ip(&[], 1), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn retry_after() {
+        let retry_after = |duration| {
+            let response = error_reply(&Error::RateLimited(duration)).unwrap();
+            assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+            response.headers()[header::RETRY_AFTER].to_str().unwrap().to_owned()
+        };
+        assert_eq!(retry_after(Duration::ZERO), "1");
+        assert_eq!(retry_after(Duration::from_millis(1200)), "2");
+        assert_eq!(retry_after(Duration::from_secs(60)), "60");
+        assert_eq!(retry_after(Duration::MAX), "86400");
+
+        let shared = Error::Shared(Arc::new(Error::RateLimited(Duration::from_secs(5))));
+        let response = error_reply(&shared).unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "5");
+    }
+}
@@ -0,0 +1,469 @@
+//! Token bucket rate limiting of register and validate calls.
+//!
+//! Buckets are keyed by `UserInfo` IP address (IPv6 by /64) and user id, and kept in memory unless another
+//! [`RateLimitStore`] is used.
+//!
+//! ```
+//! use geetest::ratelimit::{RateLimit, RateLimitPolicy, RateLimiter, RateLimits};
+//!
+//! # fn main() -> Result<(), geetest::Error> {
+//! let limits = RateLimits {
+//!     register: RateLimitPolicy {
+//!         per_ip: Some(RateLimit::per_minute(30)),
+//!         per_user: None,
+//!     },
+//!     validate: RateLimitPolicy {
+//!         per_ip: Some(RateLimit::per_minute(10)),
+//!         per_user: Some(RateLimit::per_minute(5)),
+//!     },
+//! };
+//! let rate_limiter = RateLimiter::in_memory(limits)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{
+    error::{Error, ParseError},
+    models::{Operation, UserInfo},
+};
+use futures_util::future::BoxFuture;
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Token bucket parameters.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RateLimit {
+    /// Bucket size, i.e. the number of requests allowed in a burst.
+    pub burst: u32,
+    /// Average number of requests allowed per second.
+    pub per_second: f64,
+}
+
+impl RateLimit {
+    pub fn per_second(requests: u32) -> Self {
+        Self {
+            burst: requests,
+            per_second: requests as f64,
+        }
+    }
+
+    pub fn per_minute(requests: u32) -> Self {
+        Self {
+            burst: requests,
+            per_second: requests as f64 / 60.0,
+        }
+    }
+
+    pub fn burst(mut self, burst: u32) -> Self {
+        self.burst = burst;
+        self
+    }
+
+    /// Fails unless both the burst and the rate are positive.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.burst == 0 || !(self.per_second > 0.0 && self.per_second.is_finite()) {
+            return Err(Error::Config(format!(
+                "Invalid rate limit of {} requests per second with burst {}",
+                self.per_second, self.burst
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Parses `<requests>/s` or `<requests>/min`, with the burst equal to `requests`.
+impl FromStr for RateLimit {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || ParseError {
+            kind: "rate limit",
+            value: s.to_owned(),
+        };
+        let (requests, unit) = s.trim().split_once('/').ok_or_else(error)?;
+        let requests = requests.trim().parse().map_err(|_| error())?;
+        let limit = match unit.trim() {
+            "s" | "sec" | "second" => Self::per_second(requests),
+            "m" | "min" | "minute" => Self::per_minute(requests),
+            _ => return Err(error()),
+        };
+        limit.validate().map_err(|_| error())?;
+        Ok(limit)
+    }
+}
+
+/// Limits applied to a single operation. A request must fit into every configured bucket.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimitPolicy {
+    pub per_ip: Option<RateLimit>,
+    pub per_user: Option<RateLimit>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimits {
+    pub register: RateLimitPolicy,
+    pub validate: RateLimitPolicy,
+}
+
+impl RateLimits {
+    pub fn validate(&self) -> Result<(), Error> {
+        [self.register, self.validate]
+            .iter()
+            .flat_map(|policy| policy.per_ip.into_iter().chain(policy.per_user))
+            .try_for_each(|limit| limit.validate())
+    }
+}
+
+/// Storage for token buckets.
+///
+/// Implement it on top of a shared store (e.g. Redis) to apply limits across several service instances.
+pub trait RateLimitStore: Send + Sync + 'static {
+    /// Takes one token from the bucket `key`, returns `Some(retry_after)` if the bucket is empty.
+    fn acquire<'a>(&'a self, key: &'a str, limit: RateLimit) -> BoxFuture<'a, Result<Option<Duration>, Error>>;
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    limit: RateLimit,
+}
+
+impl Bucket {
+    fn tokens_at(&self, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        (self.tokens + elapsed * self.limit.per_second).min(self.limit.burst as f64)
+    }
+
+    /// A full bucket is the same as a missing one, so it can be dropped.
+    fn is_full(&self, now: Instant) -> bool {
+        self.tokens_at(now) >= self.limit.burst as f64
+    }
+}
+
+struct Buckets {
+    map: HashMap<String, Bucket>,
+    next_sweep: Instant,
+}
+
+impl Buckets {
+    /// Drops the `count` least recently used buckets.
+    fn evict_least_recent(&mut self, count: usize) {
+        let mut by_age: Vec<(Instant, &String)> = self.map.iter().map(|(key, bucket)| (bucket.updated, key)).collect();
+        let count = count.min(by_age.len());
+        if count == 0 {
+            return;
+        }
+        by_age.select_nth_unstable(count - 1);
+        let evicted: Vec<String> = by_age[..count].iter().map(|(_, key)| (*key).clone()).collect();
+        for key in evicted {
+            self.map.remove(&key);
+        }
+    }
+}
+
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Process-local token buckets.
+pub struct InMemoryStore {
+    buckets: Mutex<Buckets>,
+    max_keys: usize,
+}
+
+impl InMemoryStore {
+    pub fn new() -> Self {
+        Self::with_max_keys(100_000)
+    }
+
+    /// Keeps at most `max_keys` buckets.
+    ///
+    /// Once the store is full, full buckets are dropped at most once a second. If that frees no room, the least
+    /// recently used eighth of the buckets is evicted, so clients rotating keys can't lock out new ones; evicted
+    /// clients start over with a full bucket.
+    pub fn with_max_keys(max_keys: usize) -> Self {
+        Self {
+            buckets: Mutex::new(Buckets {
+                map: HashMap::new(),
+                next_sweep: Instant::now(),
+            }),
+            max_keys,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Buckets> {
+        self.buckets.lock().unwrap_or_else(|error| error.into_inner())
+    }
+
+    fn take(&self, key: &str, limit: RateLimit, now: Instant) -> Option<Duration> {
+        let mut buckets = self.lock();
+        if buckets.map.len() >= self.max_keys && !buckets.map.contains_key(key) {
+            if now >= buckets.next_sweep {
+                buckets.map.retain(|_, bucket| !bucket.is_full(now));
+                buckets.next_sweep = now + SWEEP_INTERVAL;
+            }
+            if buckets.map.len() >= self.max_keys {
+                // Evicting in batches keeps the scan amortized over many new keys.
+                let count = buckets.map.len() + 1 - self.max_keys + self.max_keys / 8;
+                buckets.evict_least_recent(count);
+            }
+        }
+
+        let bucket = buckets.map.entry(key.to_owned()).or_insert(Bucket {
+            tokens: limit.burst as f64,
+            updated: now,
+            limit,
+        });
+        bucket.tokens = bucket.tokens_at(now).min(limit.burst as f64);
+        bucket.updated = now;
+        bucket.limit = limit;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            None
+        } else {
+            // Zero or tiny rates give an infinite or too long wait.
+            Some(Duration::try_from_secs_f64((1.0 - bucket.tokens) / limit.per_second).unwrap_or(Duration::MAX))
+        }
+    }
+}
+
+impl Default for InMemoryStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RateLimitStore for InMemoryStore {
+    fn acquire<'a>(&'a self, key: &'a str, limit: RateLimit) -> BoxFuture<'a, Result<Option<Duration>, Error>> {
+        Box::pin(futures_util::future::ready(Ok(self.take(key, limit, Instant::now()))))
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimiter {
+    limits: RateLimits,
+    store: Arc<dyn RateLimitStore>,
+}
+
+impl RateLimiter {
+    /// Fails if any of the limits is not positive, see [`RateLimit::validate`].
+    pub fn new(limits: RateLimits, store: impl RateLimitStore) -> Result<Self, Error> {
+        limits.validate()?;
+        Ok(Self {
+            limits,
+            store: Arc::new(store),
+        })
+    }
+
+    pub fn in_memory(limits: RateLimits) -> Result<Self, Error> {
+        Self::new(limits, InMemoryStore::new())
+    }
+
+    pub fn limits(&self) -> &RateLimits {
+        &self.limits
+    }
+
+    /// Returns `Some(retry_after)` if the request exceeds any of the operation limits.
+    ///
+    /// Store errors are logged and the request is let through.
+    pub async fn check(&self, operation: Operation, user_info: &UserInfo) -> Option<Duration> {
+        let policy = match operation {
+            Operation::Register => self.limits.register,
+            Operation::Validate => self.limits.validate,
+        };
+
+        let buckets = [
+            policy
+                .per_ip
+                .zip(user_info.ip_address)
+                .map(|(limit, ip)| (limit, format!("{}:ip:{}", operation.as_str(), ip_key(ip)))),
+            policy
+                .per_user
+                .zip(user_info.user_id.as_ref())
+                .map(|(limit, user_id)| (limit, format!("{}:user:{}", operation.as_str(), user_id))),
+        ];
+
+        let mut retry_after = None;
+        for (limit, key) in buckets.iter().flatten() {
+            match self.store.acquire(key, *limit).await {
+                Ok(Some(wait)) => retry_after = retry_after.max(Some(wait)),
+                Ok(None) => {},
+                Err(error) => tracing::warn!(error = %error, "rate limit store failed, request allowed"),
+            }
+        }
+        retry_after
+    }
+}
+
+/// IPv6 clients are limited by /64 network, as a single host usually gets a whole /64.
+fn ip_key(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => ip.to_string(),
+            None => {
+                let segments = ip.segments();
+                format!(
+                    "{:x}:{:x}:{:x}:{:x}::/64",
+                    segments[0], segments[1], segments[2], segments[3]
+                )
+            },
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seconds(secs: f64) -> Duration {
+        Duration::from_secs_f64(secs)
+    }
+
+    #[test]
+    fn burst_then_refill() {
+        let store = InMemoryStore::new();
+        let limit = RateLimit::per_second(2).burst(3);
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            assert_eq!(store.take("key", limit, start), None);
+        }
+        assert_eq!(store.take("key", limit, start), Some(seconds(0.5)));
+        assert_eq!(store.take("other", limit, start), None);
+
+        assert_eq!(store.take("key", limit, start + seconds(0.25)), Some(seconds(0.25)));
+        assert_eq!(store.take("key", limit, start + seconds(0.5)), None);
+        assert_eq!(store.take("key", limit, start + seconds(0.5)), Some(seconds(0.5)));
+
+        // Refill is capped by the burst.
+        let later = start + seconds(60.0);
+        for _ in 0..3 {
+            assert_eq!(store.take("key", limit, later), None);
+        }
+        assert!(store.take("key", limit, later).is_some());
+    }
+
+    #[test]
+    fn retry_after_saturates() {
+        let store = InMemoryStore::new();
+        let now = Instant::now();
+        let zero = RateLimit::per_second(1).burst(1);
+        assert_eq!(store.take("zero", zero, now), None);
+
+        let zero = RateLimit {
+            per_second: 0.0,
+            ..zero
+        };
+        assert_eq!(store.take("zero", zero, now), Some(Duration::MAX));
+
+        let tiny = RateLimit {
+            burst: 1,
+            per_second: 1e-300,
+        };
+        assert_eq!(store.take("tiny", tiny, now), None);
+        assert_eq!(store.take("tiny", tiny, now), Some(Duration::MAX));
+    }
+
+    #[test]
+    fn buckets_keep_own_limits() {
+        let store = InMemoryStore::with_max_keys(2);
+        let now = Instant::now();
+        let slow = RateLimit::per_minute(1);
+        let fast = RateLimit::per_second(10);
+
+        assert_eq!(store.take("slow", slow, now), None);
+        assert_eq!(store.take("fast", fast, now), None);
+
+        // `fast` refilled after 0.1s, `slow` did not, and is kept although its capacity is smaller.
+        let later = now + SWEEP_INTERVAL;
+        assert_eq!(store.take("new", fast, later), None);
+        assert_eq!(store.len(), 2);
+        assert!(store.take("slow", slow, later).is_some());
+    }
+
+    #[test]
+    fn bounded_eviction() {
+        let store = InMemoryStore::with_max_keys(3);
+        let limit = RateLimit::per_second(1);
+        let now = Instant::now();
+
+        for (i, key) in ["a", "b", "c"].iter().enumerate() {
+            assert_eq!(store.take(key, limit, now + seconds(0.1 * i as f64)), None);
+        }
+        // No bucket is full, so the least recently used one makes room for the new key.
+        let at = now + seconds(0.5);
+        assert_eq!(store.take("d", limit, at), None);
+        assert_eq!(store.len(), 3);
+        assert_eq!(store.take("b", limit, at), Some(seconds(0.6)));
+        assert_eq!(store.take("c", limit, at), Some(seconds(0.7)));
+        // "a" was evicted and starts over with a full bucket.
+        assert_eq!(store.take("a", limit, at), None);
+
+        // All buckets refilled, the sweep makes room.
+        let later = at + seconds(1.0);
+        assert_eq!(store.take("e", limit, later), None);
+        assert_eq!(store.len(), 1);
+    }
+
+    #[test]
+    fn invalid_limits() {
+        assert!(RateLimit::per_second(1).validate().is_ok());
+        assert!(RateLimit::per_second(0).validate().is_err());
+        assert!(RateLimit::per_minute(10).burst(0).validate().is_err());
+        for per_second in [0.0, -1.0, f64::NAN, f64::INFINITY].iter().copied() {
+            assert!(RateLimit { burst: 1, per_second }.validate().is_err());
+        }
+
+        let limits = RateLimits {
+            validate: RateLimitPolicy {
+                per_ip: None,
+                per_user: Some(RateLimit::per_second(0)),
+            },
+            ..RateLimits::default()
+        };
+        assert!(matches!(RateLimiter::in_memory(limits), Err(Error::Config(_))));
+    }
+
+    #[tokio::test]
+    async fn ipv6_limited_by_network() {
+        let limit = RateLimit::per_minute(1);
+        let limiter = RateLimiter::in_memory(RateLimits {
+            register: RateLimitPolicy {
+                per_ip: Some(limit),
+                per_user: None,
+            },
+            ..RateLimits::default()
+        })
+        .unwrap();
+        let user = |ip: &str| UserInfo::new().ip_address(ip.parse::<IpAddr>().unwrap());
+
+        assert_eq!(limiter.check(Operation::Register, &user("2001:db8:1:2::1")).await, None);
+        assert!(limiter
+            .check(Operation::Register, &user("2001:db8:1:2::ffff"))
+            .await
+            .is_some());
+        assert_eq!(limiter.check(Operation::Register, &user("2001:db8:1:3::1")).await, None);
+
+        assert_eq!(limiter.check(Operation::Register, &user("203.0.113.7")).await, None);
+        assert!(limiter
+            .check(Operation::Register, &user("::ffff:203.0.113.7"))
+            .await
+            .is_some());
+    }
+}
@@ -0,0 +1,150 @@
+use futures_util::future::BoxFuture;
+use hyper::{
+    client::connect::dns::{GaiResolver, Name},
+    service::Service,
+};
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    task::{Context, Poll},
+    vec,
+};
+
+/// Host name resolution for outbound GeeTest connections, e.g. backed by an internal DNS server.
+///
+/// Ports are taken from the request URL, so only addresses are returned.
+pub trait Resolve: Send + Sync + 'static {
+    fn resolve(&self, host: &str) -> BoxFuture<'_, io::Result<Vec<IpAddr>>>;
+}
+
+impl<F> Resolve for F
+where
+    F: Fn(&str) -> io::Result<Vec<IpAddr>> + Send + Sync + 'static,
+{
+    fn resolve(&self, host: &str) -> BoxFuture<'_, io::Result<Vec<IpAddr>>> {
+        Box::pin(futures_util::future::ready(self(host)))
+    }
+}
+
+/// Static overrides first, then the custom resolver or the system one (`getaddrinfo`).
+#[derive(Clone)]
+pub(crate) struct Resolver {
+    overrides: Arc<HashMap<String, Vec<IpAddr>>>,
+    custom: Option<Arc<dyn Resolve>>,
+    system: GaiResolver,
+}
+
+impl Resolver {
+    pub(crate) fn new(overrides: HashMap<String, Vec<IpAddr>>, custom: Option<Arc<dyn Resolve>>) -> Self {
+        Self {
+            overrides: Arc::new(overrides),
+            custom,
+            system: GaiResolver::new(),
+        }
+    }
+}
+
+impl Service<Name> for Resolver {
+    type Response = vec::IntoIter<SocketAddr>;
+    type Error = io::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        let to_socket_addrs = |addrs: Vec<IpAddr>| -> io::Result<Self::Response> {
+            if addrs.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::NotFound, "no addresses resolved"));
+            }
+            // Port is replaced by the connector.
+            Ok(addrs
+                .into_iter()
+                .map(|addr| SocketAddr::new(addr, 0))
+                .collect::<Vec<_>>()
+                .into_iter())
+        };
+
+        if let Some(addrs) = self.overrides.get(&name.as_str().to_ascii_lowercase()) {
+            let result = to_socket_addrs(addrs.clone());
+            return Box::pin(async move { result });
+        }
+        match self.custom.clone() {
+            Some(custom) => Box::pin(async move { to_socket_addrs(custom.resolve(name.as_str()).await?) }),
+            None => {
+                let resolving = self.system.call(name);
+                Box::pin(async move { Ok(resolving.await?.collect::<Vec<_>>().into_iter()) })
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn resolver(overrides: &[(&str, IpAddr)]) -> Resolver {
+        let mut map: HashMap<String, Vec<IpAddr>> = HashMap::new();
+        for (host, addr) in overrides {
+            map.entry(host.to_string()).or_default().push(*addr);
+        }
+        let custom = |host: &str| match host {
+            "api.geetest.com" | "internal.example" => Ok(vec![[10, 0, 0, 2].into()]),
+            "empty.example" => Ok(Vec::new()),
+            _ => Err(io::Error::new(io::ErrorKind::NotFound, host.to_owned())),
+        };
+        Resolver::new(map, Some(Arc::new(custom)))
+    }
+
+    async fn resolve(resolver: &mut Resolver, host: &str) -> io::Result<Vec<IpAddr>> {
+        let addrs = resolver.call(Name::from_str(host).unwrap()).await?;
+        Ok(addrs.map(|addr| addr.ip()).collect())
+    }
+
+    #[tokio::test]
+    async fn overrides_win() {
+        let mut resolver = resolver(&[
+            ("api.geetest.com", [10, 0, 0, 1].into()),
+            ("api.geetest.com", [10, 0, 0, 3].into()),
+        ]);
+        assert_eq!(resolve(&mut resolver, "api.geetest.com").await.unwrap(), vec![
+            IpAddr::from([10, 0, 0, 1]),
+            IpAddr::from([10, 0, 0, 3])
+        ]);
+        assert_eq!(resolve(&mut resolver, "API.GeeTest.com").await.unwrap(), vec![
+            IpAddr::from([10, 0, 0, 1]),
+            IpAddr::from([10, 0, 0, 3])
+        ]);
+    }
+
+    #[tokio::test]
+    async fn falls_through_to_custom() {
+        let mut resolver = resolver(&[("api.geetest.com", [10, 0, 0, 1].into())]);
+        assert_eq!(resolve(&mut resolver, "internal.example").await.unwrap(), vec![
+            IpAddr::from([10, 0, 0, 2])
+        ]);
+        let error = resolve(&mut resolver, "unknown.example").await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[tokio::test]
+    async fn empty_addresses_rejected() {
+        let mut resolver = resolver(&[]);
+        let error = resolve(&mut resolver, "empty.example").await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        assert_eq!(error.to_string(), "no addresses resolved");
+
+        let mut resolver = Resolver::new(
+            vec![("empty.example".to_owned(), Vec::new())].into_iter().collect(),
+            None,
+        );
+        assert_eq!(
+            resolve(&mut resolver, "empty.example").await.unwrap_err().to_string(),
+            "no addresses resolved"
+        );
+    }
+}
@@ -0,0 +1,75 @@
+use serde::{Deserialize, Deserializer};
+use std::fmt;
+use zeroize::Zeroize;
+
+static REDACTED: &str = "<redacted>";
+
+/// GeeTest captcha secret (private key).
+///
+/// The value is never printed by `Debug` or `Display` and is wiped from memory on drop.
+/// Use [`CaptchaSecret::expose_secret`] to get the raw value where it is really needed.
+#[derive(Clone, PartialEq, Eq)]
+pub struct CaptchaSecret(String);
+
+impl CaptchaSecret {
+    pub fn new(secret: impl Into<String>) -> Self {
+        Self(secret.into())
+    }
+
+    pub fn expose_secret(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for CaptchaSecret {
+    fn from(secret: String) -> Self {
+        Self(secret)
+    }
+}
+
+impl From<&str> for CaptchaSecret {
+    fn from(secret: &str) -> Self {
+        Self(secret.to_owned())
+    }
+}
+
+impl<'de> Deserialize<'de> for CaptchaSecret {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        String::deserialize(d).map(Self)
+    }
+}
+
+impl fmt::Debug for CaptchaSecret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("CaptchaSecret")
+            .field(&format_args!("{}", REDACTED))
+            .finish()
+    }
+}
+
+impl fmt::Display for CaptchaSecret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl Drop for CaptchaSecret {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn never_printed() {
+        let secret = CaptchaSecret::new("geetest-captcha-secret");
+        assert_eq!(format!("{:?}", secret), "CaptchaSecret(<redacted>)");
+        assert!(!format!("{:#?}", secret).contains("geetest-captcha-secret"));
+        assert_eq!(secret.to_string(), "<redacted>");
+        assert_eq!(format!("{:>30}", secret).trim(), "<redacted>");
+        assert_eq!(secret.expose_secret(), "geetest-captcha-secret");
+    }
+}
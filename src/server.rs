@@ -1,162 +1,732 @@
+//! Captcha server for the GeeTest front-end library.
+//!
+//! [`Server`] answers `GET /register` and `POST /validate`, plus:
+//!
+//! - `GET /healthz` with `{"status":"ok"}` while the process is alive;
+//! - `GET /readyz` with the bypass status and circuit state, see [`Handler::check_readiness`]. It returns 503 while
+//!   fail-back challenges are issued, unless built with [`Server::ready_in_failback`];
+//! - `GET /metrics` and the embedded `gt.js`, with the `metrics` and `assets` features.
+//!
+//! Every request is handled in a `geetest.http` span carrying the `X-Request-Id` header value (generated if
+//! missing), which is echoed back. [`Handler`] spans (`geetest.handle`) carry a `challenge_hash` fingerprint, never
+//! the raw challenge.
+
+#[cfg(all(feature = "runtime", feature = "assets"))]
+use crate::assets::{self, Assets};
+#[cfg(feature = "runtime")]
+use crate::client::Client;
+#[cfg(feature = "metrics")]
+use crate::metrics::Metrics;
+#[cfg(feature = "session-binding")]
+use crate::session::SessionBinder;
+#[cfg(all(feature = "runtime", feature = "session-binding"))]
+use crate::session::{MAX_COOKIE_BINDINGS, SESSION_COOKIE};
+#[cfg(feature = "pass-token")]
+use crate::token::PassTokenIssuer;
 use crate::{
-    client::Client,
+    backend::CaptchaBackend,
+    breaker::{CircuitBreaker, CircuitState},
+    digest::{self, challenge_hash, ChallengeDigest},
     error::Error,
-    models::{ClientRegisterResponse, ClientValidateRequest, ClientValidateResponse, DigestMod, UserInfo},
+    events::{CaptchaEvent, EventKind, EventSink},
+    models::{
+        BypassState, ClientRegisterResponse, ClientValidateRequest, ClientValidateResponse, Endpoint, Operation,
+        Outcome, Readiness, UserInfo,
+    },
+    protocol,
+    ratelimit::RateLimiter,
+    secret::CaptchaSecret,
+    singleflight::SingleFlight,
 };
+#[cfg(feature = "runtime")]
 use futures_util::{FutureExt, TryFutureExt};
+#[cfg(all(
+    feature = "runtime",
+    any(feature = "assets", feature = "metrics", feature = "session-binding")
+))]
+use hyper::header;
+#[cfg(feature = "runtime")]
 use hyper::{
     body::{Bytes, HttpBody},
-    header,
+    header::HeaderValue,
+    server::conn::AddrStream,
     service::{make_service_fn, Service},
     Body, Method, Request, Response, StatusCode,
 };
-use rand::seq::SliceRandom;
-use serde::{de::DeserializeOwned, Serialize};
+#[cfg(feature = "runtime")]
+use serde::Serialize;
+#[cfg(feature = "runtime")]
 use std::{
     convert::Infallible,
-    future::Future,
+    net::SocketAddr,
     pin::Pin,
-    sync::Arc,
     task::{Context, Poll},
 };
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+#[cfg(feature = "runtime")]
 use tokio::net::ToSocketAddrs;
+use tracing::{field::Empty, Instrument, Span};
+
+pub static REQUEST_ID_HEADER: &str = "x-request-id";
+/// Bypass status checks older than this are repeated by [`Handler::check_readiness`].
+pub const READINESS_RECHECK_INTERVAL: Duration = Duration::from_secs(10);
+pub use crate::protocol::FORWARDED_FOR_HEADER;
 
+#[cfg(feature = "runtime")]
 #[derive(Clone)]
 pub struct Server {
     handler: Handler,
+    remote_addr: Option<SocketAddr>,
+    trusted_proxies: usize,
+    ready_in_failback: bool,
+    #[cfg(feature = "session-binding")]
+    secure_cookie: bool,
+    #[cfg(feature = "assets")]
+    assets: Option<Arc<Assets>>,
+    #[cfg(feature = "metrics")]
+    metrics_route: bool,
 }
 
 struct HandlerImpl {
-    client: Client,
-    captcha_secret: String,
+    backend: Arc<dyn CaptchaBackend>,
+    captcha_secret: CaptchaSecret,
+    digest: Arc<dyn ChallengeDigest>,
+    failback_on_error: bool,
+    rate_limiter: Option<RateLimiter>,
+    circuit_breaker: Option<Arc<CircuitBreaker>>,
+    event_sinks: Vec<Arc<dyn EventSink>>,
+    #[cfg(feature = "pass-token")]
+    pass_token_issuer: Option<PassTokenIssuer>,
+    #[cfg(feature = "session-binding")]
+    session_binder: Option<SessionBinder>,
+    #[cfg(feature = "metrics")]
+    metrics: Option<Metrics>,
+    status_flight: Option<SingleFlight<bool>>,
+    readiness_flight: SingleFlight<bool>,
+    bypass_check: Mutex<(BypassState, Option<u64>)>,
 }
 
 #[derive(Clone)]
 pub struct Handler(Arc<HandlerImpl>);
 
+pub struct HandlerBuilder {
+    backend: Arc<dyn CaptchaBackend>,
+    captcha_secret: CaptchaSecret,
+    digest: Option<Arc<dyn ChallengeDigest>>,
+    failback_on_error: bool,
+    rate_limiter: Option<RateLimiter>,
+    circuit_breaker: Option<Arc<CircuitBreaker>>,
+    event_sinks: Vec<Arc<dyn EventSink>>,
+    #[cfg(feature = "pass-token")]
+    pass_token_issuer: Option<PassTokenIssuer>,
+    #[cfg(feature = "session-binding")]
+    session_binder: Option<SessionBinder>,
+    #[cfg(feature = "metrics")]
+    metrics: Option<Metrics>,
+    coalesce_bypass_status: bool,
+}
+
+impl HandlerBuilder {
+    pub fn digest(mut self, digest: impl ChallengeDigest) -> Self {
+        self.digest = Some(Arc::new(digest));
+        self
+    }
+
+    /// Issue fail-back challenges instead of returning an error when GeeTest API is unreachable.
+    pub fn failback_on_error(mut self, enabled: bool) -> Self {
+        self.failback_on_error = enabled;
+        self
+    }
+
+    /// Limit register and validate calls by `UserInfo` IP address and user id.
+    pub fn rate_limiter(mut self, rate_limiter: RateLimiter) -> Self {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
+
+    /// Switch into fail-back mode while the circuit is open, regardless of `failback_on_error`.
+    pub fn circuit_breaker(mut self, circuit_breaker: CircuitBreaker) -> Self {
+        self.circuit_breaker = Some(Arc::new(circuit_breaker));
+        self
+    }
+
+    /// Let concurrent requests share a single in-flight bypass status call and its result.
+    ///
+    /// The shared call goes through the circuit breaker and metrics once. Its errors are returned as
+    /// [`Error::Shared`].
+    pub fn coalesce_bypass_status(mut self, enabled: bool) -> Self {
+        self.coalesce_bypass_status = enabled;
+        self
+    }
+
+    /// Adds a sink receiving every captcha decision. Can be called several times.
+    pub fn event_sink(mut self, sink: impl EventSink) -> Self {
+        self.event_sinks.push(Arc::new(sink));
+        self
+    }
+
+    /// Return a signed pass token in successful validate responses, see [`crate::token::PassTokenVerifier`].
+    #[cfg(feature = "pass-token")]
+    pub fn pass_token_issuer(mut self, issuer: PassTokenIssuer) -> Self {
+        self.pass_token_issuer = Some(issuer);
+        self
+    }
+
+    /// Bind register challenges to sessions and reject validation from other sessions.
+    ///
+    /// Once set, validation requires a session, see [`Handler::handle_validate_for_session`]. [`Server`] keeps the
+    /// session in an HttpOnly cookie.
+    #[cfg(feature = "session-binding")]
+    pub fn session_binder(mut self, binder: SessionBinder) -> Self {
+        self.session_binder = Some(binder);
+        self
+    }
+
+    #[cfg(feature = "metrics")]
+    pub fn metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Fails if no digest was set and the built-in digest for the backend's `DigestMod` is disabled.
+    pub fn build(mut self) -> Result<Handler, Error> {
+        let digestmod = self.backend.digestmod();
+        let digest = self
+            .digest
+            .take()
+            .or_else(|| digest::builtin(digestmod))
+            .ok_or_else(|| Error::Config(format!("Digest {} is disabled, use HandlerBuilder::digest", digestmod)))?;
+        Ok(self.build_with(digest))
+    }
+
+    fn build_with(self, digest: Arc<dyn ChallengeDigest>) -> Handler {
+        Handler(Arc::new(HandlerImpl {
+            backend: self.backend,
+            captcha_secret: self.captcha_secret,
+            digest,
+            failback_on_error: self.failback_on_error,
+            rate_limiter: self.rate_limiter,
+            circuit_breaker: self.circuit_breaker,
+            event_sinks: self.event_sinks,
+            #[cfg(feature = "pass-token")]
+            pass_token_issuer: self.pass_token_issuer,
+            #[cfg(feature = "session-binding")]
+            session_binder: self.session_binder,
+            #[cfg(feature = "metrics")]
+            metrics: self.metrics,
+            status_flight: self.coalesce_bypass_status.then(SingleFlight::new),
+            readiness_flight: SingleFlight::new(),
+            bypass_check: Mutex::new((BypassState::Unknown, None)),
+        }))
+    }
+}
+
 impl Handler {
-    pub fn new(captcha_id: impl Into<String>, captcha_secret: impl Into<String>) -> Self {
-        Self::from_client(Client::new(captcha_id, DigestMod::Md5), captcha_secret)
+    #[cfg(all(feature = "runtime", feature = "digest-md5"))]
+    pub fn new(captcha_id: impl Into<String>, captcha_secret: impl Into<CaptchaSecret>) -> Self {
+        Self::with_digest(
+            Client::new(captcha_id, crate::models::DigestMod::Md5),
+            captcha_secret,
+            digest::Md5Digest,
+        )
     }
 
-    pub fn from_client(client: Client, captcha_secret: impl Into<String>) -> Self {
-        Self(Arc::new(HandlerImpl {
-            client,
+    /// Uses the built-in digest for the client's `DigestMod`.
+    ///
+    /// Fails if the corresponding `digest-*` feature is disabled, use [`Handler::with_digest`] in that case.
+    #[cfg(feature = "runtime")]
+    pub fn from_client(client: Client, captcha_secret: impl Into<CaptchaSecret>) -> Result<Self, Error> {
+        Self::builder(client, captcha_secret).build()
+    }
+
+    /// Uses any [`CaptchaBackend`], e.g. [`FakeBackend`](crate::backend::FakeBackend) in tests.
+    pub fn from_backend(backend: impl CaptchaBackend, captcha_secret: impl Into<CaptchaSecret>) -> Result<Self, Error> {
+        Self::builder(backend, captcha_secret).build()
+    }
+
+    pub fn with_digest(
+        backend: impl CaptchaBackend,
+        captcha_secret: impl Into<CaptchaSecret>,
+        digest: impl ChallengeDigest,
+    ) -> Self {
+        Self::builder(backend, captcha_secret).build_with(Arc::new(digest))
+    }
+
+    pub fn builder(backend: impl CaptchaBackend, captcha_secret: impl Into<CaptchaSecret>) -> HandlerBuilder {
+        HandlerBuilder {
+            backend: Arc::new(backend),
             captcha_secret: captcha_secret.into(),
-        }))
+            digest: None,
+            failback_on_error: false,
+            rate_limiter: None,
+            circuit_breaker: None,
+            event_sinks: Vec::new(),
+            #[cfg(feature = "pass-token")]
+            pass_token_issuer: None,
+            #[cfg(feature = "session-binding")]
+            session_binder: None,
+            #[cfg(feature = "metrics")]
+            metrics: None,
+            coalesce_bypass_status: false,
+        }
+    }
+
+    pub fn circuit_state(&self) -> Option<CircuitState> {
+        self.0.circuit_breaker.as_ref().map(|breaker| breaker.state())
+    }
+
+    /// Derived from the last bypass status check and circuit state, no GeeTest API call is made.
+    ///
+    /// With `ready_in_failback` the handler is reported ready while it issues fail-back challenges.
+    pub fn readiness(&self, ready_in_failback: bool) -> Readiness {
+        let (bypass_status, bypass_checked_at_ms) =
+            *self.0.bypass_check.lock().unwrap_or_else(|error| error.into_inner());
+        let circuit_state = self.circuit_state();
+        let circuit_open = circuit_state == Some(CircuitState::Open);
+
+        let failback = match bypass_status {
+            BypassState::Bypassed => true,
+            BypassState::Error => self.0.failback_on_error || circuit_open,
+            BypassState::Unknown | BypassState::Online => circuit_open,
+        };
+        let healthy = matches!(bypass_status, BypassState::Unknown | BypassState::Online) && !circuit_open;
+
+        Readiness {
+            ready: healthy || (failback && ready_in_failback),
+            failback,
+            bypass_status,
+            bypass_checked_at_ms,
+            circuit_state,
+        }
+    }
+
+    /// Like [`Handler::readiness`], but checks bypass status again first if the last check is older than
+    /// [`READINESS_RECHECK_INTERVAL`] or the circuit cooldown expired. Concurrent checks share a single call.
+    pub async fn check_readiness(&self, ready_in_failback: bool) -> Readiness {
+        if self.0.readiness_stale() {
+            // Failures are reflected in the bypass status and circuit state.
+            let _ = self.0.readiness_flight.run(|| self.0.bypass_status()).await;
+        }
+        self.readiness(ready_in_failback)
+    }
+
+    #[cfg(feature = "metrics")]
+    pub fn metrics(&self) -> Option<&Metrics> {
+        self.0.metrics.as_ref()
+    }
+
+    #[cfg(feature = "session-binding")]
+    pub fn session_binder(&self) -> Option<&SessionBinder> {
+        self.0.session_binder.as_ref()
     }
 
     pub fn handle_register(self) -> impl Future<Output = Result<ClientRegisterResponse, Error>> + Send + 'static {
-        self.0.handle_register()
+        self.handle_register_with(UserInfo::default())
+    }
+
+    pub fn handle_register_with(
+        self,
+        user_info: UserInfo,
+    ) -> impl Future<Output = Result<ClientRegisterResponse, Error>> + Send + 'static {
+        self.0.handle_register(user_info, None)
+    }
+
+    /// Fills `session_binding` of the response, the client has to send it back on validate.
+    #[cfg(feature = "session-binding")]
+    pub fn handle_register_for_session(
+        self,
+        user_info: UserInfo,
+        session_id: impl Into<String>,
+    ) -> impl Future<Output = Result<ClientRegisterResponse, Error>> + Send + 'static {
+        self.0.handle_register(user_info, Some(session_id.into()))
     }
 
     pub fn handle_validate(
         self,
         request: ClientValidateRequest,
     ) -> impl Future<Output = Result<ClientValidateResponse, Error>> + Send + 'static {
-        self.0.handle_validate(request)
+        self.handle_validate_with(request, UserInfo::default())
+    }
+
+    pub fn handle_validate_with(
+        self,
+        request: ClientValidateRequest,
+        user_info: UserInfo,
+    ) -> impl Future<Output = Result<ClientValidateResponse, Error>> + Send + 'static {
+        self.0.handle_validate(request, user_info, None)
+    }
+
+    /// Rejects the request unless its `session_binding` proves the challenge was issued to `session_id`.
+    #[cfg(feature = "session-binding")]
+    pub fn handle_validate_for_session(
+        self,
+        request: ClientValidateRequest,
+        user_info: UserInfo,
+        session_id: impl Into<String>,
+    ) -> impl Future<Output = Result<ClientValidateResponse, Error>> + Send + 'static {
+        self.0.handle_validate(request, user_info, Some(session_id.into()))
     }
 }
 
 impl HandlerImpl {
-    async fn handle_register(self: Arc<Self>) -> Result<ClientRegisterResponse, Error> {
-        log::debug!("handle register");
-
-        let bypass_status = self.client.bypass_status().await?;
-
-        if bypass_status {
-            let origin_challenge = self.client.register(UserInfo::default()).await?;
-            log::debug!("origin challenge: {}", origin_challenge);
-
-            let challenge = match self.client.digestmod {
-                #[cfg(feature = "digest-md5")]
-                DigestMod::Md5 => {
-                    let mut hasher = md5::Context::new();
-                    hasher.consume(origin_challenge);
-                    hasher.consume(&self.captcha_secret);
-                    let digest = hasher.compute();
-                    format!("{:x}", digest)
-                },
-                #[cfg(feature = "digest-sha256")]
-                DigestMod::Sha256 => {
-                    use sha2::Digest;
-                    let mut hasher = sha2::Sha256::new();
-                    hasher.update(origin_challenge);
-                    hasher.update(&self.captcha_secret);
-                    let digest = hasher.finalize();
-                    format!("{:x}", digest)
+    async fn handle_register(
+        self: Arc<Self>,
+        user_info: UserInfo,
+        session_id: Option<String>,
+    ) -> Result<ClientRegisterResponse, Error> {
+        let span = Self::span(&*self.backend, Operation::Register);
+        async move {
+            let started = Instant::now();
+            let result = self
+                .register(user_info.clone())
+                .await
+                .map(|(response, outcome)| (self.bind_session(response, session_id.as_deref()), outcome));
+            let challenge_hash = result
+                .as_ref()
+                .ok()
+                .map(|(response, _)| challenge_hash(&response.challenge));
+            self.record(Operation::Register, &result, started, user_info, challenge_hash);
+            result.map(|(response, _)| response)
+        }
+        .instrument(span)
+        .await
+    }
+
+    async fn handle_validate(
+        self: Arc<Self>,
+        req: ClientValidateRequest,
+        user_info: UserInfo,
+        session_id: Option<String>,
+    ) -> Result<ClientValidateResponse, Error> {
+        let span = Self::span(&*self.backend, Operation::Validate);
+        async move {
+            let started = Instant::now();
+            let challenge_hash = challenge_hash(&req.challenge);
+            let result = self.validate(req, user_info.clone(), session_id).await;
+            self.record(Operation::Validate, &result, started, user_info, Some(challenge_hash));
+            result.map(|(response, _)| response)
+        }
+        .instrument(span)
+        .await
+    }
+
+    fn span(backend: &dyn CaptchaBackend, operation: Operation) -> Span {
+        tracing::info_span!(
+            "geetest.handle",
+            operation = operation.as_str(),
+            captcha_id = %backend.captcha_id(),
+            challenge_hash = Empty,
+            outcome = Empty,
+            latency_ms = Empty,
+        )
+    }
+
+    async fn register(&self, user_info: UserInfo) -> Result<(ClientRegisterResponse, Outcome), Error> {
+        self.check_rate_limit(Operation::Register, &user_info).await?;
+
+        if self.bypass_status().await? {
+            match self
+                .call_api(Endpoint::Register, self.backend.register(user_info))
+                .await
+            {
+                Ok(Some(origin_challenge)) => {
+                    let response = protocol::online_register_reply(
+                        self.backend.captcha_id(),
+                        &origin_challenge,
+                        &*self.digest,
+                        &self.captcha_secret,
+                    );
+                    return Ok((response, Outcome::Success));
                 },
-                #[cfg(feature = "digest-hmac-sha256")]
-                DigestMod::HmacSha256 => {
-                    use hmac::{Mac, NewMac};
-                    let mut hasher = hmac::Hmac::<sha2::Sha256>::new_from_slice(origin_challenge.as_bytes())
-                        .expect("HMAC can take key of any size");
-                    hasher.update(self.captcha_secret.as_bytes());
-                    let digest = hasher.finalize();
-                    format!("{:x}", digest.into_bytes())
+                Ok(None) => tracing::warn!("geetest register returned no challenge, using fail-back mode"),
+                Err(error) if self.fails_back(&error) => {
+                    tracing::warn!(error = %error, "geetest register failed, using fail-back mode");
                 },
-            };
-
-            Ok(ClientRegisterResponse {
-                success: true,
-                new_captcha: true,
-                challenge,
-                captcha_id: self.client.captcha_id.clone(),
-            })
-        } else {
-            let challenge = "abcdefghijklmnopqrstuvwxyz0123456789"
-                .as_bytes()
-                .choose_multiple(&mut rand::thread_rng(), 32)
-                .copied()
-                .map(|b| b as char)
-                .collect();
-            Ok(ClientRegisterResponse {
-                success: false,
-                captcha_id: self.client.captcha_id.clone(),
-                new_captcha: true,
-                challenge,
-            })
+                Err(error) => return Err(error),
+            }
         }
+
+        Ok((
+            protocol::failback_register_reply(self.backend.captcha_id()),
+            Outcome::Failback,
+        ))
     }
 
-    async fn handle_validate(self: Arc<Self>, req: ClientValidateRequest) -> Result<ClientValidateResponse, Error> {
-        let is_valid_request =
-            !(req.challenge.trim().is_empty() || req.validate.trim().is_empty() || req.seccode.trim().is_empty());
+    async fn validate(
+        &self,
+        req: ClientValidateRequest,
+        user_info: UserInfo,
+        session_id: Option<String>,
+    ) -> Result<(ClientValidateResponse, Outcome), Error> {
+        if let Some(rejection) = protocol::check_validate_request(&req) {
+            return Ok((rejection, Outcome::Rejected));
+        }
 
-        if !is_valid_request {
-            return Ok(ClientValidateResponse::error("Invalid request fields"));
+        if !self.session_matches(&req, session_id.as_deref()) {
+            return Ok((
+                ClientValidateResponse::error("Challenge does not belong to the session"),
+                Outcome::Rejected,
+            ));
         }
 
-        let bypass_status = self.client.bypass_status().await?;
+        self.check_rate_limit(Operation::Validate, &user_info).await?;
 
-        if bypass_status {
+        if self.bypass_status().await? {
             let seccode = self
-                .client
-                .validate(req.seccode, req.challenge, UserInfo::default())
+                .call_api(
+                    Endpoint::Validate,
+                    self.backend.validate(req.seccode, req.challenge, user_info.clone()),
+                )
                 .await?;
 
-            if let Some(_) = seccode {
-                Ok(ClientValidateResponse::success())
-            } else {
-                Ok(ClientValidateResponse::error("Invalid security code"))
+            match seccode {
+                Some(_) => Ok((self.passed(&user_info, false), Outcome::Success)),
+                None => Ok((protocol::rejected_reply(), Outcome::Rejected)),
             }
         } else {
-            Ok(ClientValidateResponse::success())
+            Ok((self.passed(&user_info, true), Outcome::Failback))
+        }
+    }
+
+    #[cfg_attr(not(feature = "session-binding"), allow(unused_variables, unused_mut))]
+    fn bind_session(&self, mut response: ClientRegisterResponse, session_id: Option<&str>) -> ClientRegisterResponse {
+        #[cfg(feature = "session-binding")]
+        if let (Some(binder), Some(session_id)) = (&self.session_binder, session_id) {
+            response.session_binding = Some(binder.bind(session_id, &response.challenge));
+        }
+        response
+    }
+
+    #[cfg_attr(not(feature = "session-binding"), allow(unused_variables))]
+    fn session_matches(&self, req: &ClientValidateRequest, session_id: Option<&str>) -> bool {
+        #[cfg(feature = "session-binding")]
+        if let Some(binder) = &self.session_binder {
+            return match (session_id, &req.session_binding) {
+                (Some(session_id), Some(binding)) => binder.verify(session_id, &req.challenge, binding),
+                _ => false,
+            };
+        }
+        true
+    }
+
+    #[cfg_attr(not(feature = "pass-token"), allow(unused_variables))]
+    fn passed(&self, user_info: &UserInfo, failback: bool) -> ClientValidateResponse {
+        let response = protocol::passed_reply();
+
+        #[cfg(feature = "pass-token")]
+        if let Some(issuer) = &self.pass_token_issuer {
+            return response.pass_token(issuer.issue(user_info, failback));
+        }
+
+        response
+    }
+
+    async fn check_rate_limit(&self, operation: Operation, user_info: &UserInfo) -> Result<(), Error> {
+        if let Some(rate_limiter) = &self.rate_limiter {
+            if let Some(retry_after) = rate_limiter.check(operation, user_info).await {
+                return Err(Error::RateLimited(retry_after));
+            }
+        }
+        Ok(())
+    }
+
+    async fn bypass_status(&self) -> Result<bool, Error> {
+        let call = || self.call_api(Endpoint::Status, self.backend.bypass_status());
+        let status = match &self.status_flight {
+            Some(status_flight) => status_flight.run(call).await,
+            None => call().await,
+        };
+
+        #[cfg(feature = "metrics")]
+        if let (Some(metrics), Ok(status)) = (&self.metrics, &status) {
+            metrics.set_bypass_status(*status);
+        }
+
+        let state = match status {
+            Ok(true) => BypassState::Online,
+            Ok(false) => BypassState::Bypassed,
+            Err(_) => BypassState::Error,
+        };
+        *self.bypass_check.lock().unwrap_or_else(|error| error.into_inner()) = (state, Some(CaptchaEvent::now()));
+
+        match status {
+            Ok(status) => Ok(status),
+            Err(error) if self.fails_back(&error) => {
+                tracing::warn!(error = %error, "geetest bypass status failed, using fail-back mode");
+                Ok(false)
+            },
+            Err(error) => Err(error),
+        }
+    }
+
+    fn readiness_stale(&self) -> bool {
+        let (_, checked_at_ms) = *self.bypass_check.lock().unwrap_or_else(|error| error.into_inner());
+        let outdated = checked_at_ms.map_or(true, |checked_at_ms| {
+            CaptchaEvent::now().saturating_sub(checked_at_ms) >= READINESS_RECHECK_INTERVAL.as_millis() as u64
+        });
+        let probe_due = self
+            .circuit_breaker
+            .as_ref()
+            .is_some_and(|breaker| breaker.state() == CircuitState::HalfOpen);
+        outdated || probe_due
+    }
+
+    fn fails_back(&self, error: &Error) -> bool {
+        self.failback_on_error || matches!(error.unshared(), Error::CircuitOpen)
+    }
+
+    #[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+    async fn call_api<T>(&self, endpoint: Endpoint, call: impl Future<Output = Result<T, Error>>) -> Result<T, Error> {
+        let permit = match &self.circuit_breaker {
+            Some(breaker) => Some(breaker.try_acquire().ok_or(Error::CircuitOpen)?),
+            None => None,
+        };
+
+        let started = Instant::now();
+        let result = call.await;
+
+        if let Some((breaker, permit)) = self.circuit_breaker.as_ref().zip(permit) {
+            breaker.record(permit, result.is_ok(), started.elapsed());
+        }
+
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = &self.metrics {
+            metrics.observe_api_latency(endpoint, started.elapsed());
+        }
+
+        result
+    }
+
+    fn record<T>(
+        &self,
+        operation: Operation,
+        result: &Result<(T, Outcome), Error>,
+        started: Instant,
+        user_info: UserInfo,
+        challenge_hash: Option<String>,
+    ) {
+        let outcome = match result {
+            Ok((_, outcome)) => *outcome,
+            Err(Error::RateLimited(_)) => Outcome::RateLimited,
+            Err(_) => Outcome::Error,
+        };
+        let latency = started.elapsed();
+
+        let span = Span::current();
+        span.record("outcome", outcome.as_str());
+        span.record("latency_ms", latency.as_millis() as u64);
+        if let Some(challenge_hash) = &challenge_hash {
+            span.record("challenge_hash", &**challenge_hash);
+        }
+        match result {
+            Ok(_) => tracing::info!(outcome = outcome.as_str(), "captcha request handled"),
+            Err(error) => tracing::warn!(error = %error, "captcha request failed"),
+        }
+
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = &self.metrics {
+            metrics.record(operation, outcome);
+        }
+
+        if !self.event_sinks.is_empty() {
+            let event = CaptchaEvent {
+                kind: EventKind::new(operation, outcome),
+                operation,
+                outcome,
+                captcha_id: self.backend.captcha_id().to_owned(),
+                user_info,
+                challenge_hash,
+                error: result.as_ref().err().map(ToString::to_string),
+                timestamp_ms: CaptchaEvent::now(),
+                latency_ms: latency.as_millis() as u64,
+            };
+            for sink in &self.event_sinks {
+                sink.emit(&event);
+            }
         }
     }
 }
 
+#[cfg(feature = "runtime")]
 impl Server {
-    pub fn new(captcha_id: impl Into<String>, captcha_secret: impl Into<String>) -> Self {
-        Self::from_client(Client::new(captcha_id, DigestMod::Md5), captcha_secret)
+    #[cfg(feature = "digest-md5")]
+    pub fn new(captcha_id: impl Into<String>, captcha_secret: impl Into<CaptchaSecret>) -> Self {
+        Self::from_handler(Handler::new(captcha_id, captcha_secret))
     }
 
-    pub fn from_client(client: Client, captcha_secret: impl Into<String>) -> Self {
-        Self::from_handler(Handler::from_client(client, captcha_secret))
+    pub fn from_client(client: Client, captcha_secret: impl Into<CaptchaSecret>) -> Result<Self, Error> {
+        Handler::from_client(client, captcha_secret).map(Self::from_handler)
     }
 
     pub fn from_handler(handler: Handler) -> Self {
-        Self { handler }
+        Self {
+            handler,
+            remote_addr: None,
+            trusted_proxies: 0,
+            ready_in_failback: false,
+            #[cfg(feature = "session-binding")]
+            secure_cookie: false,
+            #[cfg(feature = "assets")]
+            assets: None,
+            #[cfg(feature = "metrics")]
+            metrics_route: false,
+        }
+    }
+
+    /// Take client IP address from `X-Forwarded-For` header, as appended by a single trusted reverse proxy.
+    ///
+    /// Enable only behind a reverse proxy, see [`Server::trusted_proxies`] for a chain of proxies.
+    pub fn trust_forwarded_for(self, enabled: bool) -> Self {
+        self.trusted_proxies(enabled as usize)
+    }
+
+    /// Number of trusted reverse proxies in front of the server appending to `X-Forwarded-For`, see
+    /// [`protocol::user_info`]. `0` (default) ignores the header.
+    pub fn trusted_proxies(mut self, hops: usize) -> Self {
+        self.trusted_proxies = hops;
+        self
+    }
+
+    /// Report ready on `GET /readyz` while fail-back challenges are issued, e.g. during GeeTest outage.
+    pub fn ready_in_failback(mut self, enabled: bool) -> Self {
+        self.ready_in_failback = enabled;
+        self
+    }
+
+    /// Peer address of the connection served, set by [`Server::run`] for every accepted connection.
+    pub fn with_remote_addr(mut self, remote_addr: SocketAddr) -> Self {
+        self.remote_addr = Some(remote_addr);
+        self
+    }
+
+    /// Mark the session cookie `Secure`. Enable when served over HTTPS.
+    #[cfg(feature = "session-binding")]
+    pub fn secure_cookie(mut self, enabled: bool) -> Self {
+        self.secure_cookie = enabled;
+        self
+    }
+
+    /// Serve the embedded `gt.js` and the demo page.
+    #[cfg(feature = "assets")]
+    pub fn assets(mut self, assets: Assets) -> Self {
+        self.assets = Some(Arc::new(assets));
+        self
+    }
+
+    /// Serve handler metrics on `GET /metrics`.
+    ///
+    /// The handler has to be built with [`HandlerBuilder::metrics`], otherwise the route answers `404 Not Found`.
+    #[cfg(feature = "metrics")]
+    pub fn metrics_route(mut self, enabled: bool) -> Self {
+        if enabled && self.handler.metrics().is_none() {
+            tracing::warn!("metrics route enabled for a handler without metrics, GET /metrics will return 404");
+        }
+        self.metrics_route = enabled;
+        self
     }
 
     pub async fn run(self, addr: impl ToSocketAddrs) -> Result<(), hyper::Error> {
@@ -168,20 +738,95 @@ impl Server {
             .expect("Socket address resolve failed");
 
         hyper::Server::bind(&addr)
-            .serve(make_service_fn(move |_| {
-                let svc = self.clone();
+            .serve(make_service_fn(move |conn: &AddrStream| {
+                let svc = self.clone().with_remote_addr(conn.remote_addr());
                 async { Ok::<_, Infallible>(svc) }
             }))
             .await
     }
 
+    fn user_info(&self, req: &Request<Body>) -> UserInfo {
+        protocol::user_info(
+            req.headers(),
+            self.remote_addr.map(|addr| addr.ip()),
+            self.trusted_proxies,
+        )
+    }
+
+    /// Takes a sane `X-Request-Id` from the request or generates a new one.
+    fn request_id(req: &Request<Body>) -> String {
+        req.headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| {
+                !value.is_empty()
+                    && value.len() <= 128
+                    && value.bytes().all(|b| b.is_ascii_alphanumeric() || b"-_.:".contains(&b))
+            })
+            .map(ToOwned::to_owned)
+            .unwrap_or_else(|| format!("{:032x}", rand::random::<u128>()))
+    }
+
+    /// Session id and challenge bindings (newest first) from the session cookie.
+    #[cfg(feature = "session-binding")]
+    fn session_cookie(req: &Request<Body>) -> Option<(String, Vec<String>)> {
+        let is_hex =
+            |value: &str| !value.is_empty() && value.len() <= 128 && value.bytes().all(|b| b.is_ascii_hexdigit());
+
+        let value = req
+            .headers()
+            .get_all(header::COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(';'))
+            .filter_map(|pair| pair.trim().split_once('='))
+            .find(|(name, _)| *name == SESSION_COOKIE)?
+            .1;
+
+        let mut parts = value.split('.');
+        let session_id = parts.next().filter(|session_id| is_hex(session_id))?;
+        let bindings = parts
+            .take(MAX_COOKIE_BINDINGS)
+            .filter(|binding| is_hex(binding))
+            .map(ToOwned::to_owned)
+            .collect();
+        Some((session_id.to_owned(), bindings))
+    }
+
+    /// Moves `session_binding` from the register response into an HttpOnly cookie, in front of the bindings of
+    /// challenges registered earlier in the session.
+    #[cfg(feature = "session-binding")]
+    async fn session_reply(
+        mut reply: ClientRegisterResponse,
+        session_id: String,
+        previous_bindings: Vec<String>,
+        secure: bool,
+    ) -> Result<Response<Body>, Error> {
+        let bindings = reply
+            .session_binding
+            .take()
+            .into_iter()
+            .chain(previous_bindings)
+            .take(MAX_COOKIE_BINDINGS)
+            .collect::<Vec<_>>();
+        let cookie = format!(
+            "{}={}.{}; Path=/; HttpOnly; SameSite=Lax{}",
+            SESSION_COOKIE,
+            session_id,
+            bindings.join("."),
+            if secure { "; Secure" } else { "" }
+        );
+
+        let mut response = Self::convert_reply(reply).await?;
+        response.headers_mut().insert(
+            header::SET_COOKIE,
+            HeaderValue::from_str(&cookie).map_err(http::Error::from)?,
+        );
+        Ok(response)
+    }
+
     async fn convert_reply<T: Serialize>(reply: T) -> Result<Response<Body>, Error> {
-        let body = serde_json::to_vec(&reply)?;
-        Response::builder()
-            .status(StatusCode::OK)
-            .header(header::CONTENT_TYPE, "application/json")
-            .body(Body::from(body))
-            .map_err(Into::into)
+        Ok(protocol::json_reply(&reply)?.map(Body::from))
     }
 
     async fn read_body(mut body: Body) -> Result<Vec<u8>, Error> {
@@ -190,8 +835,66 @@ impl Server {
             .await
     }
 
-    async fn parse_body<T: DeserializeOwned>(body: Vec<u8>) -> Result<T, Error> {
-        serde_qs::from_bytes(&body).map_err(Into::into)
+    async fn parse_body(body: Vec<u8>) -> Result<ClientValidateRequest, Error> {
+        protocol::parse_validate_body(&body)
+    }
+
+    #[cfg(feature = "metrics")]
+    async fn metrics_reply(metrics: Metrics) -> Result<Response<Body>, Error> {
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, crate::metrics::TEXT_FORMAT)
+            .body(Body::from(metrics.encode()?))
+            .map_err(Into::into)
+    }
+
+    async fn health_reply() -> Result<Response<Body>, Error> {
+        Self::convert_reply(serde_json::json!({ "status": "ok" })).await
+    }
+
+    async fn readiness_reply(readiness: Readiness) -> Result<Response<Body>, Error> {
+        let mut response = Self::convert_reply(readiness).await?;
+        if !readiness.ready {
+            *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+        }
+        Ok(response)
+    }
+
+    #[cfg(feature = "assets")]
+    async fn gt_js_reply(if_none_match: Option<HeaderValue>) -> Result<Response<Body>, Error> {
+        let etag = assets::gt_js_etag();
+        let response = Response::builder()
+            .header(header::ETAG, &etag)
+            .header(header::CACHE_CONTROL, "public, max-age=86400");
+
+        let not_modified = if_none_match
+            .as_ref()
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| {
+                // If-None-Match uses the weak comparison, so `W/` validators match too.
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+            });
+        if not_modified {
+            return Ok(response.status(StatusCode::NOT_MODIFIED).body(Body::empty())?);
+        }
+
+        Ok(response
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "text/javascript; charset=utf-8")
+            .body(Body::from(assets::GT_JS))?)
+    }
+
+    #[cfg(feature = "assets")]
+    async fn demo_page_reply(assets: Arc<Assets>) -> Result<Response<Body>, Error> {
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+            .header(header::CACHE_CONTROL, "no-cache")
+            .body(Body::from(assets.demo_page()))
+            .map_err(Into::into)
     }
 
     async fn bad_request() -> Result<Response<Body>, Error> {
@@ -201,21 +904,20 @@ impl Server {
             .map_err(Into::into)
     }
 
-    async fn handle_error(error: Error) -> Result<Response<Body>, Error> {
-        let error_body = ClientValidateResponse::error(error.to_string());
-
-        let status_code = match error {
-            Error::Query(_) | Error::Json(_) => StatusCode::BAD_REQUEST,
-            _ => StatusCode::INTERNAL_SERVER_ERROR,
-        };
+    #[cfg(feature = "metrics")]
+    async fn not_found() -> Result<Response<Body>, Error> {
+        Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty())
+            .map_err(Into::into)
+    }
 
-        Ok(Response::builder()
-            .status(status_code)
-            .header(header::CONTENT_TYPE, "application/json")
-            .body(Body::from(serde_json::to_vec(&error_body)?))?)
+    async fn handle_error(error: Error) -> Result<Response<Body>, Error> {
+        Ok(protocol::error_reply(&error)?.map(Body::from))
     }
 }
 
+#[cfg(feature = "runtime")]
 impl Service<Request<Body>> for Server {
     type Response = Response<Body>;
     type Error = Error;
@@ -226,29 +928,899 @@ impl Service<Request<Body>> for Server {
     }
 
     fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let request_id = Self::request_id(&req);
+        let span = tracing::info_span!(
+            "geetest.http",
+            request_id = %request_id,
+            method = %req.method(),
+            path = %req.uri().path(),
+            status = Empty,
+        );
+        let reply = span.in_scope(|| self.route(req));
+
+        Box::pin(
+            async move {
+                let mut response = reply.await?;
+                Span::current().record("status", response.status().as_u16());
+                if let Ok(request_id) = HeaderValue::from_str(&request_id) {
+                    response.headers_mut().insert(REQUEST_ID_HEADER, request_id);
+                }
+                Ok(response)
+            }
+            .instrument(span),
+        )
+    }
+}
+
+#[cfg(feature = "runtime")]
+impl Server {
+    fn route(&mut self, req: Request<Body>) -> <Self as Service<Request<Body>>>::Future {
         let route = (req.method(), req.uri().path());
-        log::debug!("Route {:?}", route);
+        tracing::debug!(?route, "route");
         match route {
+            #[cfg(feature = "session-binding")]
+            (&Method::GET, "/register") if self.handler.session_binder().is_some() => {
+                let handler = self.handler.clone();
+                let user_info = self.user_info(&req);
+                let secure = self.secure_cookie;
+                let (session_id, bindings) =
+                    Self::session_cookie(&req).unwrap_or_else(|| (SessionBinder::new_session_id(), Vec::new()));
+                Box::pin(
+                    handler
+                        .handle_register_for_session(user_info, session_id.clone())
+                        .and_then(move |reply| Self::session_reply(reply, session_id, bindings, secure))
+                        .or_else(Self::handle_error),
+                )
+            },
+            #[cfg(feature = "session-binding")]
+            (&Method::POST, "/validate") if self.handler.session_binder().is_some() => {
+                let handler = self.handler.clone();
+                let user_info = self.user_info(&req);
+                let (session_id, bindings) = Self::session_cookie(&req).unwrap_or_default();
+                Box::pin(
+                    Self::read_body(req.into_body())
+                        .and_then(Self::parse_body)
+                        .and_then(move |mut body: ClientValidateRequest| {
+                            // The binding of this challenge, if any.
+                            let binder = handler.session_binder();
+                            body.session_binding = bindings.into_iter().find(|binding| {
+                                binder.is_some_and(|binder| binder.verify(&session_id, &body.challenge, binding))
+                            });
+                            handler.handle_validate_for_session(body, user_info, session_id)
+                        })
+                        .and_then(Self::convert_reply)
+                        .or_else(Self::handle_error),
+                )
+            },
             (&Method::GET, "/register") => {
                 let handler = self.handler.clone();
+                let user_info = self.user_info(&req);
                 Box::pin(
                     handler
-                        .handle_register()
+                        .handle_register_with(user_info)
                         .and_then(Self::convert_reply)
                         .or_else(Self::handle_error),
                 )
             },
             (&Method::POST, "/validate") => {
                 let handler = self.handler.clone();
+                let user_info = self.user_info(&req);
                 Box::pin(
                     Self::read_body(req.into_body())
                         .and_then(Self::parse_body)
-                        .and_then(|body| handler.handle_validate(body))
+                        .and_then(|body| handler.handle_validate_with(body, user_info))
                         .and_then(Self::convert_reply)
                         .or_else(Self::handle_error),
                 )
             },
+            (&Method::GET, "/healthz") => Box::pin(Self::health_reply().or_else(Self::handle_error)),
+            (&Method::GET, "/readyz") => {
+                let handler = self.handler.clone();
+                let ready_in_failback = self.ready_in_failback;
+                Box::pin(
+                    async move { handler.check_readiness(ready_in_failback).await }
+                        .then(Self::readiness_reply)
+                        .or_else(Self::handle_error),
+                )
+            },
+            #[cfg(feature = "metrics")]
+            (&Method::GET, "/metrics") if self.metrics_route => match self.handler.metrics() {
+                Some(metrics) => Box::pin(Self::metrics_reply(metrics.clone()).or_else(Self::handle_error)),
+                None => Box::pin(Self::not_found()),
+            },
+            #[cfg(feature = "assets")]
+            (&Method::GET, path) if self.assets.as_ref().is_some_and(|assets| assets.gt_js_path == path) => Box::pin(
+                Self::gt_js_reply(req.headers().get(header::IF_NONE_MATCH).cloned()).or_else(Self::handle_error),
+            ),
+            #[cfg(feature = "assets")]
+            (&Method::GET, path)
+                if self
+                    .assets
+                    .as_ref()
+                    .is_some_and(|assets| assets.demo_path.as_deref() == Some(path)) =>
+            {
+                let assets = self.assets.clone().expect("Assets are set");
+                Box::pin(Self::demo_page_reply(assets).or_else(Self::handle_error))
+            },
             _ => Box::pin(Self::bad_request()),
         }
     }
 }
+
+#[cfg(all(test, feature = "runtime"))]
+mod tests {
+    use super::*;
+    use crate::{backend::FakeBackend, breaker::CircuitBreakerConfig, models::DigestMod};
+    use futures_util::future::{join_all, BoxFuture};
+    use hyper::{header, HeaderMap};
+    use std::{
+        io,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    const SECRET: &str = "geetest-captcha-secret";
+
+    fn digest(origin_challenge: &str, _: &CaptchaSecret) -> String {
+        format!("digest-{}", origin_challenge)
+    }
+
+    /// Bypass status calls take a while and fail, other calls are never made.
+    #[derive(Default)]
+    struct SlowFailingStatus {
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl CaptchaBackend for SlowFailingStatus {
+        fn captcha_id(&self) -> &str {
+            "captcha-id"
+        }
+
+        fn digestmod(&self) -> DigestMod {
+            DigestMod::Md5
+        }
+
+        fn bypass_status(&self) -> BoxFuture<'_, Result<bool, Error>> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                Err(io::Error::new(io::ErrorKind::ConnectionRefused, "unavailable").into())
+            })
+        }
+
+        fn register(&self, _: UserInfo) -> BoxFuture<'_, Result<Option<String>, Error>> {
+            unreachable!("bypass status fails")
+        }
+
+        fn validate(&self, _: String, _: String, _: UserInfo) -> BoxFuture<'_, Result<Option<String>, Error>> {
+            unreachable!("bypass status fails")
+        }
+    }
+
+    /// Online, but register returns no challenge.
+    struct NoChallenge;
+
+    impl CaptchaBackend for NoChallenge {
+        fn captcha_id(&self) -> &str {
+            "captcha-id"
+        }
+
+        fn digestmod(&self) -> DigestMod {
+            DigestMod::Md5
+        }
+
+        fn bypass_status(&self) -> BoxFuture<'_, Result<bool, Error>> {
+            Box::pin(async { Ok(true) })
+        }
+
+        fn register(&self, _: UserInfo) -> BoxFuture<'_, Result<Option<String>, Error>> {
+            Box::pin(async { Ok(None) })
+        }
+
+        fn validate(&self, _: String, _: String, _: UserInfo) -> BoxFuture<'_, Result<Option<String>, Error>> {
+            unreachable!("not validated")
+        }
+    }
+
+    fn breaker(failure_threshold: u32) -> CircuitBreaker {
+        breaker_with_cooldown(failure_threshold, Duration::from_secs(60))
+    }
+
+    fn breaker_with_cooldown(failure_threshold: u32, cooldown: Duration) -> CircuitBreaker {
+        CircuitBreaker::new(CircuitBreakerConfig {
+            failure_threshold,
+            slow_call_ms: None,
+            cooldown_ms: cooldown.as_millis() as u64,
+        })
+    }
+
+    fn fake_handler() -> (FakeBackend, HandlerBuilder) {
+        let backend = FakeBackend::new("captcha-id", DigestMod::Md5);
+        let builder = Handler::builder(backend.clone(), SECRET).digest(digest);
+        (backend, builder)
+    }
+
+    fn validate_request(challenge: &str, seccode: &str) -> ClientValidateRequest {
+        ClientValidateRequest {
+            challenge: challenge.to_owned(),
+            validate: "validate".to_owned(),
+            seccode: seccode.to_owned(),
+            session_binding: None,
+        }
+    }
+
+    async fn get(server: &mut Server, path: &str) -> Response<Body> {
+        let req = Request::get(path).body(Body::empty()).unwrap();
+        server.call(req).await.unwrap()
+    }
+
+    async fn send(server: &mut Server, req: Request<Body>) -> (StatusCode, HeaderMap, serde_json::Value) {
+        let response = server.call(req).await.unwrap();
+        let (parts, body) = response.into_parts();
+        let body = hyper::body::to_bytes(body).await.unwrap();
+        (parts.status, parts.headers, serde_json::from_slice(&body).unwrap())
+    }
+
+    fn validate_form(challenge: &str, seccode: &str) -> Body {
+        Body::from(format!(
+            "geetest_challenge={}&geetest_validate=validate&geetest_seccode={}",
+            challenge, seccode
+        ))
+    }
+
+    #[tokio::test]
+    async fn coalesced_bypass_status_is_recorded_once() {
+        let backend = SlowFailingStatus::default();
+        let calls = backend.calls.clone();
+        let handler = Handler::builder(backend, SECRET)
+            .digest(digest)
+            .circuit_breaker(breaker(2))
+            .coalesce_bypass_status(true)
+            .build()
+            .unwrap();
+
+        let results = join_all((0..3).map(|_| handler.clone().handle_register())).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        for result in results {
+            assert!(matches!(result.unwrap_err(), Error::Shared(error) if matches!(*error, Error::Io(_))));
+        }
+        assert_eq!(handler.circuit_state(), Some(CircuitState::Closed));
+    }
+
+    #[tokio::test]
+    async fn uncoalesced_bypass_status_is_recorded_per_call() {
+        let backend = SlowFailingStatus::default();
+        let calls = backend.calls.clone();
+        let handler = Handler::builder(backend, SECRET)
+            .digest(digest)
+            .circuit_breaker(breaker(2))
+            .build()
+            .unwrap();
+
+        let results = join_all((0..3).map(|_| handler.clone().handle_register())).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        assert!(results.iter().all(Result::is_err));
+        assert_eq!(handler.circuit_state(), Some(CircuitState::Open));
+    }
+
+    #[tokio::test]
+    async fn readiness_recovers_after_cooldown() {
+        let backend = FakeBackend::new("captcha-id", DigestMod::Md5);
+        let handler = Handler::builder(backend.clone(), SECRET)
+            .digest(digest)
+            .circuit_breaker(breaker_with_cooldown(1, Duration::from_millis(10)))
+            .build()
+            .unwrap();
+
+        backend.set_unavailable(true);
+        assert!(handler.clone().handle_register().await.is_err());
+        let readiness = handler.check_readiness(false).await;
+        assert!(!readiness.ready);
+        assert_eq!(readiness.bypass_status, BypassState::Error);
+        assert_eq!(readiness.circuit_state, Some(CircuitState::Open));
+
+        backend.set_unavailable(false);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(handler.circuit_state(), Some(CircuitState::HalfOpen));
+        let readiness = handler.check_readiness(false).await;
+        assert!(readiness.ready);
+        assert_eq!(readiness.bypass_status, BypassState::Online);
+        assert_eq!(readiness.circuit_state, Some(CircuitState::Closed));
+    }
+
+    #[tokio::test]
+    async fn readyz_checks_bypass_status() {
+        let backend = FakeBackend::new("captcha-id", DigestMod::Md5);
+        let handler = Handler::builder(backend.clone(), SECRET)
+            .digest(digest)
+            .build()
+            .unwrap();
+        assert_eq!(handler.readiness(false).bypass_checked_at_ms, None);
+
+        backend.set_online(false);
+        let mut server = Server::from_handler(handler.clone());
+        assert_eq!(
+            get(&mut server, "/readyz").await.status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(handler.readiness(false).bypass_status, BypassState::Bypassed);
+
+        let mut server = server.ready_in_failback(true);
+        assert_eq!(get(&mut server, "/readyz").await.status(), StatusCode::OK);
+
+        // Checked recently, the cached result is reused.
+        backend.set_online(true);
+        assert_eq!(get(&mut server, "/readyz").await.status(), StatusCode::OK);
+        assert_eq!(handler.readiness(false).bypass_status, BypassState::Bypassed);
+    }
+
+    #[tokio::test]
+    async fn online_register_and_validate() {
+        let (backend, builder) = fake_handler();
+        let handler = builder.build().unwrap();
+
+        let reply = handler.clone().handle_register().await.unwrap();
+        assert!(reply.success && reply.new_captcha);
+        assert_eq!(reply.captcha_id, "captcha-id");
+        assert_eq!(reply.challenge, "digest-fake-challenge-0");
+        assert_eq!(backend.registered(), 1);
+
+        let reply = handler
+            .handle_validate(validate_request(&reply.challenge, "seccode"))
+            .await
+            .unwrap();
+        assert!(reply.result);
+        assert_eq!(reply.msg, None);
+    }
+
+    #[tokio::test]
+    async fn failback_register_and_validate() {
+        let (backend, builder) = fake_handler();
+        let handler = builder.build().unwrap();
+        backend.set_online(false).approve_by_default(false);
+
+        let reply = handler.clone().handle_register().await.unwrap();
+        assert!(!reply.success);
+        assert_eq!(reply.challenge.len(), 32);
+        assert_eq!(backend.registered(), 0);
+
+        // GeeTest is not asked in fail-back mode.
+        let reply = handler
+            .clone()
+            .handle_validate(validate_request(&reply.challenge, "seccode"))
+            .await
+            .unwrap();
+        assert!(reply.result);
+
+        backend.set_online(true).set_unavailable(true);
+        assert!(matches!(handler.handle_register().await, Err(Error::Io(_))));
+    }
+
+    #[tokio::test]
+    async fn failback_without_challenge() {
+        let handler = Handler::with_digest(NoChallenge, SECRET, digest);
+        let reply = handler.handle_register().await.unwrap();
+        assert!(!reply.success);
+        assert_eq!(reply.challenge.len(), 32);
+    }
+
+    #[tokio::test]
+    async fn failback_on_error() {
+        let (backend, builder) = fake_handler();
+        let handler = builder.failback_on_error(true).build().unwrap();
+        backend.set_unavailable(true);
+
+        assert!(!handler.clone().handle_register().await.unwrap().success);
+        let reply = handler
+            .handle_validate(validate_request("challenge", "seccode"))
+            .await
+            .unwrap();
+        assert!(reply.result);
+    }
+
+    #[tokio::test]
+    async fn rejected_seccode() {
+        let (backend, builder) = fake_handler();
+        let handler = builder.build().unwrap();
+        backend.reject("bad-seccode");
+
+        let reply = handler
+            .clone()
+            .handle_validate(validate_request("challenge", "bad-seccode"))
+            .await
+            .unwrap();
+        assert!(!reply.result);
+        assert_eq!(reply.msg.as_deref(), Some("Invalid security code"));
+
+        let reply = handler
+            .clone()
+            .handle_validate(validate_request("challenge", " "))
+            .await
+            .unwrap();
+        assert!(!reply.result);
+        assert_eq!(reply.msg.as_deref(), Some("Invalid request fields"));
+
+        let mut server = Server::from_handler(handler);
+        let req = Request::post("/validate")
+            .body(validate_form("challenge", "bad-seccode"))
+            .unwrap();
+        let (status, _, reply) = send(&mut server, req).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(reply["result"], "fail");
+    }
+
+    #[tokio::test]
+    async fn rate_limited() {
+        use crate::ratelimit::{RateLimit, RateLimitPolicy, RateLimits};
+
+        let limits = RateLimits {
+            register: RateLimitPolicy {
+                per_ip: Some(RateLimit::per_minute(1)),
+                per_user: None,
+            },
+            validate: RateLimitPolicy {
+                per_ip: None,
+                per_user: Some(RateLimit::per_minute(1)),
+            },
+        };
+        let (_, builder) = fake_handler();
+        let handler = builder
+            .rate_limiter(RateLimiter::in_memory(limits).unwrap())
+            .build()
+            .unwrap();
+        let client = UserInfo::new().ip_address([203, 0, 113, 7].into());
+        let other_client = UserInfo::new().ip_address([203, 0, 113, 8].into());
+
+        assert!(handler.clone().handle_register_with(client.clone()).await.is_ok());
+        assert!(matches!(
+            handler.clone().handle_register_with(client).await,
+            Err(Error::RateLimited(_))
+        ));
+        assert!(handler.clone().handle_register_with(other_client).await.is_ok());
+
+        let user = UserInfo::new().user_id("user");
+        let request = validate_request("challenge", "seccode");
+        assert!(handler
+            .clone()
+            .handle_validate_with(request.clone(), user.clone())
+            .await
+            .is_ok());
+        assert!(matches!(
+            handler.handle_validate_with(request, user).await,
+            Err(Error::RateLimited(_))
+        ));
+    }
+
+    #[cfg(feature = "assets")]
+    #[tokio::test]
+    async fn gt_js_served() {
+        let (_, builder) = fake_handler();
+        let mut server = Server::from_handler(builder.build().unwrap()).assets(Assets::default());
+        let etag = assets::gt_js_etag();
+
+        let response = get(&mut server, "/static/gt.js").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::ETAG], *etag);
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "public, max-age=86400");
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, assets::GT_JS);
+
+        let weak = format!("W/{}", etag);
+        let list = format!("\"other\", {}", etag);
+        for if_none_match in [&*etag, &*weak, &*list, "*"].iter() {
+            let req = Request::get("/static/gt.js")
+                .header(header::IF_NONE_MATCH, *if_none_match)
+                .body(Body::empty())
+                .unwrap();
+            let response = server.call(req).await.unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_MODIFIED, "{}", if_none_match);
+            assert_eq!(response.headers()[header::ETAG], *etag);
+        }
+
+        let req = Request::get("/static/gt.js")
+            .header(header::IF_NONE_MATCH, "\"other\"")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(server.call(req).await.unwrap().status(), StatusCode::OK);
+
+        let response = get(&mut server, "/").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert!(std::str::from_utf8(&body).unwrap().contains("initGeetest"));
+    }
+
+    #[tokio::test]
+    async fn events_emitted() {
+        use crate::{
+            events::ChannelSink,
+            ratelimit::{RateLimit, RateLimitPolicy, RateLimits},
+        };
+
+        let limits = RateLimits {
+            register: RateLimitPolicy {
+                per_ip: None,
+                per_user: Some(RateLimit::per_minute(2)),
+            },
+            validate: RateLimitPolicy::default(),
+        };
+        let (sink, mut events) = ChannelSink::new(16);
+        let (backend, builder) = fake_handler();
+        let handler = builder
+            .rate_limiter(RateLimiter::in_memory(limits).unwrap())
+            .event_sink(sink)
+            .build()
+            .unwrap();
+        let user = UserInfo::new().user_id("user").ip_address([203, 0, 113, 7].into());
+        let mut next_event = |kind: EventKind, challenge: Option<&str>| {
+            let event = events.try_recv().unwrap();
+            assert_eq!(event.kind, kind);
+            assert_eq!(event.captcha_id, "captcha-id");
+            assert!(event.user_info == user);
+            assert_eq!(event.challenge_hash, challenge.map(challenge_hash));
+            assert!(event.latency_ms < 1000);
+            event
+        };
+
+        let reply = handler.clone().handle_register_with(user.clone()).await.unwrap();
+        let event = next_event(EventKind::RegisterIssued, Some(&reply.challenge));
+        assert_eq!(
+            (event.operation, event.outcome),
+            (Operation::Register, Outcome::Success)
+        );
+        assert_eq!(event.error, None);
+
+        backend.reject("bad-seccode");
+        for (seccode, kind) in [
+            ("seccode", EventKind::ValidatePassed),
+            ("bad-seccode", EventKind::ValidateRejected),
+        ]
+        .iter()
+        {
+            let request = validate_request(&reply.challenge, seccode);
+            handler
+                .clone()
+                .handle_validate_with(request, user.clone())
+                .await
+                .unwrap();
+            next_event(*kind, Some(&reply.challenge));
+        }
+
+        backend.set_online(false);
+        let reply = handler.clone().handle_register_with(user.clone()).await.unwrap();
+        let event = next_event(EventKind::FailbackUsed, Some(&reply.challenge));
+        assert_eq!(event.outcome, Outcome::Failback);
+
+        backend.set_online(true).set_unavailable(true);
+        let request = validate_request("challenge", "seccode");
+        assert!(handler
+            .clone()
+            .handle_validate_with(request, user.clone())
+            .await
+            .is_err());
+        let event = next_event(EventKind::ApiError, Some("challenge"));
+        assert!(event.error.unwrap().contains("fake backend is unavailable"));
+
+        assert!(handler.handle_register_with(user.clone()).await.is_err());
+        let event = next_event(EventKind::RateLimited, None);
+        assert_eq!(
+            (event.operation, event.outcome),
+            (Operation::Register, Outcome::RateLimited)
+        );
+        assert!(events.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn event_latency() {
+        use crate::events::ChannelSink;
+
+        let (sink, mut events) = ChannelSink::new(1);
+        let handler = Handler::builder(SlowFailingStatus::default(), SECRET)
+            .digest(digest)
+            .event_sink(sink)
+            .build()
+            .unwrap();
+
+        assert!(handler.handle_register().await.is_err());
+        let event = events.try_recv().unwrap();
+        assert_eq!(event.kind, EventKind::ApiError);
+        assert!(event.latency_ms >= 20);
+    }
+
+    #[tokio::test]
+    async fn forwarded_for_spoofing_does_not_escape_rate_limit() {
+        use crate::ratelimit::{RateLimit, RateLimitPolicy, RateLimits};
+
+        let limits = RateLimits {
+            register: RateLimitPolicy {
+                per_ip: Some(RateLimit::per_minute(1)),
+                per_user: None,
+            },
+            validate: RateLimitPolicy::default(),
+        };
+        let (_, builder) = fake_handler();
+        let handler = builder
+            .rate_limiter(RateLimiter::in_memory(limits).unwrap())
+            .build()
+            .unwrap();
+        let mut server = Server::from_handler(handler)
+            .trusted_proxies(1)
+            .with_remote_addr(([10, 0, 0, 1], 40000).into());
+
+        let register = |forwarded_for: &str| {
+            Request::get("/register")
+                .header(FORWARDED_FOR_HEADER, forwarded_for)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let (status, ..) = send(&mut server, register("198.51.100.1, 203.0.113.7")).await;
+        assert_eq!(status, StatusCode::OK);
+        // The client controls entries left of the one appended by the proxy.
+        let (status, headers, _) = send(&mut server, register("198.51.100.2, 203.0.113.7")).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert!(headers.contains_key(header::RETRY_AFTER));
+
+        let (status, ..) = send(&mut server, register("203.0.113.7, 203.0.113.8")).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[cfg(feature = "pass-token")]
+    #[tokio::test]
+    async fn pass_token_issued() {
+        use crate::{error::PassTokenError, token::PassTokenVerifier};
+
+        let (backend, builder) = fake_handler();
+        let handler = builder
+            .pass_token_issuer(PassTokenIssuer::new("token-key", "login", Duration::from_secs(60)))
+            .build()
+            .unwrap();
+        let verifier = PassTokenVerifier::new("token-key", "login");
+        let user = UserInfo::new().user_id("user").ip_address([203, 0, 113, 7].into());
+
+        let reply = handler
+            .clone()
+            .handle_validate_with(validate_request("challenge", "seccode"), user.clone())
+            .await
+            .unwrap();
+        let token = reply.pass_token.unwrap();
+        assert!(!verifier.verify(&token, &user).unwrap().failback);
+        assert_eq!(
+            PassTokenVerifier::new("other-key", "login").verify(&token, &user),
+            Err(PassTokenError::BadSignature)
+        );
+
+        backend.reject("bad-seccode");
+        let reply = handler
+            .clone()
+            .handle_validate_with(validate_request("challenge", "bad-seccode"), user.clone())
+            .await
+            .unwrap();
+        assert_eq!(reply.pass_token, None);
+
+        backend.set_online(false);
+        let reply = handler
+            .handle_validate_with(validate_request("challenge", "seccode"), user.clone())
+            .await
+            .unwrap();
+        let token = reply.pass_token.unwrap();
+        assert_eq!(verifier.verify(&token, &user), Err(PassTokenError::Failback));
+        assert!(verifier.accept_failback(true).verify(&token, &user).unwrap().failback);
+    }
+
+    #[cfg(feature = "session-binding")]
+    fn session_server() -> (FakeBackend, Server) {
+        let (backend, builder) = fake_handler();
+        let handler = builder
+            .session_binder(SessionBinder::new("session-key"))
+            .build()
+            .unwrap();
+        (backend, Server::from_handler(handler))
+    }
+
+    #[cfg(feature = "session-binding")]
+    async fn register_in_session(server: &mut Server, cookie: Option<&str>) -> (String, String) {
+        let mut req = Request::get("/register");
+        if let Some(cookie) = cookie {
+            req = req.header(header::COOKIE, cookie);
+        }
+        let (status, headers, reply) = send(server, req.body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(reply.get("session_binding").is_none(), "binding is kept in the cookie");
+        let set_cookie = headers[header::SET_COOKIE].to_str().unwrap();
+        let cookie = set_cookie.split(';').next().unwrap().to_owned();
+        (reply["challenge"].as_str().unwrap().to_owned(), cookie)
+    }
+
+    #[cfg(feature = "session-binding")]
+    async fn validate_in_session(server: &mut Server, challenge: &str, cookie: &str) -> bool {
+        let req = Request::post("/validate")
+            .header(header::COOKIE, cookie)
+            .body(validate_form(challenge, "seccode"))
+            .unwrap();
+        let (status, _, reply) = send(server, req).await;
+        assert_eq!(status, StatusCode::OK);
+        reply["result"] == "success"
+    }
+
+    #[cfg(feature = "session-binding")]
+    #[tokio::test]
+    async fn session_binding_mismatch_rejected() {
+        let (_, mut server) = session_server();
+        let (challenge, cookie) = register_in_session(&mut server, None).await;
+        let (_, other_cookie) = register_in_session(&mut server, None).await;
+
+        assert!(!validate_in_session(&mut server, &challenge, &other_cookie).await);
+        assert!(validate_in_session(&mut server, &challenge, &cookie).await);
+
+        let handler = server.handler.clone();
+        let request = validate_request(&challenge, "seccode");
+        let reply = handler
+            .handle_validate_for_session(request, UserInfo::default(), "0123")
+            .await
+            .unwrap();
+        assert_eq!(reply.msg.as_deref(), Some("Challenge does not belong to the session"));
+    }
+
+    #[cfg(feature = "session-binding")]
+    #[tokio::test]
+    async fn session_cookie_keeps_several_tabs() {
+        let (_, mut server) = session_server();
+        let (first, cookie) = register_in_session(&mut server, None).await;
+        let (second, cookie) = register_in_session(&mut server, Some(&cookie)).await;
+        assert_eq!(cookie.split('.').count(), 3, "session id and two bindings");
+
+        assert!(validate_in_session(&mut server, &first, &cookie).await);
+        assert!(validate_in_session(&mut server, &second, &cookie).await);
+
+        let mut cookie = cookie;
+        for _ in 0..MAX_COOKIE_BINDINGS {
+            cookie = register_in_session(&mut server, Some(&cookie)).await.1;
+        }
+        assert_eq!(cookie.split('.').count(), 1 + MAX_COOKIE_BINDINGS);
+        assert!(
+            !validate_in_session(&mut server, &first, &cookie).await,
+            "oldest binding dropped"
+        );
+    }
+
+    #[cfg(feature = "metrics")]
+    fn metric(metrics: &Metrics, name: &str, labels: &[(&str, &str)]) -> prometheus::proto::Metric {
+        let family = metrics
+            .registry()
+            .gather()
+            .into_iter()
+            .find(|family| family.get_name() == name)
+            .unwrap_or_else(|| panic!("no {} metric", name));
+        family
+            .get_metric()
+            .iter()
+            .find(|metric| {
+                labels.iter().all(|(name, value)| {
+                    metric
+                        .get_label()
+                        .iter()
+                        .any(|label| label.get_name() == *name && label.get_value() == *value)
+                })
+            })
+            .cloned()
+            .unwrap_or_else(|| panic!("no {}{:?} metric", name, labels))
+    }
+
+    #[cfg(feature = "metrics")]
+    fn requests(metrics: &Metrics, operation: &str, outcome: &str) -> f64 {
+        metric(metrics, "geetest_requests_total", &[
+            ("operation", operation),
+            ("outcome", outcome),
+        ])
+        .get_counter()
+        .get_value()
+    }
+
+    #[cfg(feature = "metrics")]
+    fn api_calls(metrics: &Metrics, endpoint: &str) -> u64 {
+        metric(metrics, "geetest_api_request_duration_seconds", &[(
+            "endpoint", endpoint,
+        )])
+        .get_histogram()
+        .get_sample_count()
+    }
+
+    #[cfg(feature = "metrics")]
+    fn bypass_status(metrics: &Metrics) -> f64 {
+        metric(metrics, "geetest_bypass_status", &[]).get_gauge().get_value()
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn metrics_recorded() {
+        let metrics = Metrics::new();
+        let (backend, builder) = fake_handler();
+        let handler = builder.metrics(metrics.clone()).build().unwrap();
+        backend.reject("bad-seccode");
+
+        handler.clone().handle_register().await.unwrap();
+        assert_eq!(requests(&metrics, "register", "success"), 1.0);
+        assert_eq!(bypass_status(&metrics), 1.0);
+
+        handler
+            .clone()
+            .handle_validate(validate_request("challenge", "seccode"))
+            .await
+            .unwrap();
+        handler
+            .clone()
+            .handle_validate(validate_request("challenge", "bad-seccode"))
+            .await
+            .unwrap();
+        assert_eq!(requests(&metrics, "validate", "success"), 1.0);
+        assert_eq!(requests(&metrics, "validate", "rejected"), 1.0);
+
+        backend.set_online(false);
+        handler.clone().handle_register().await.unwrap();
+        handler
+            .clone()
+            .handle_validate(validate_request("challenge", "seccode"))
+            .await
+            .unwrap();
+        assert_eq!(requests(&metrics, "register", "failback"), 1.0);
+        assert_eq!(requests(&metrics, "validate", "failback"), 1.0);
+        assert_eq!(bypass_status(&metrics), 0.0);
+
+        backend.set_online(true).set_unavailable(true);
+        handler.clone().handle_register().await.unwrap_err();
+        assert_eq!(requests(&metrics, "register", "error"), 1.0);
+        assert_eq!(
+            bypass_status(&metrics),
+            0.0,
+            "failed status calls leave the gauge alone"
+        );
+
+        // Every handled call checked the bypass status, failed calls included.
+        assert_eq!(api_calls(&metrics, "status"), 6);
+        assert_eq!(api_calls(&metrics, "register"), 1);
+        assert_eq!(api_calls(&metrics, "validate"), 2);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn metrics_route() {
+        let metrics = Metrics::new();
+        let (_, builder) = fake_handler();
+        let handler = builder.metrics(metrics).build().unwrap();
+        let mut server = Server::from_handler(handler).metrics_route(true);
+
+        assert_eq!(get(&mut server, "/register").await.status(), StatusCode::OK);
+        let response = get(&mut server, "/metrics").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], crate::metrics::TEXT_FORMAT);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        for line in [
+            "# TYPE geetest_requests_total counter",
+            "geetest_requests_total{operation=\"register\",outcome=\"success\"} 1",
+            "# TYPE geetest_api_request_duration_seconds histogram",
+            "geetest_api_request_duration_seconds_count{endpoint=\"register\"} 1",
+            "geetest_api_request_duration_seconds_count{endpoint=\"status\"} 1",
+            "# TYPE geetest_bypass_status gauge",
+            "geetest_bypass_status 1",
+        ] {
+            assert!(
+                body.lines().any(|body_line| body_line == line),
+                "{} not in\n{}",
+                line,
+                body
+            );
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn metrics_route_needs_handler_metrics() {
+        let (_, builder) = fake_handler();
+        let handler = builder.build().unwrap();
+
+        let mut server = Server::from_handler(handler.clone()).metrics_route(true);
+        assert_eq!(get(&mut server, "/metrics").await.status(), StatusCode::NOT_FOUND);
+
+        let mut server = Server::from_handler(handler);
+        assert_eq!(get(&mut server, "/metrics").await.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,124 @@
+//! Binding of challenges to the session that requested them.
+//!
+//! A challenge obtained in one browser session can't be validated from another. [`Server`] keeps the session id
+//! and the bindings of the last [`MAX_COOKIE_BINDINGS`] challenges in the [`SESSION_COOKIE`] HttpOnly cookie, so
+//! captchas opened in several tabs can all be validated. With [`Handler`] directly, pass your own session id to
+//! [`Handler::handle_register_for_session`] and send `session_binding` of the reply back as
+//! `geetest_session_binding` on validate.
+//!
+//! [`Server`]: crate::server::Server
+//! [`Handler`]: crate::server::Handler
+//! [`Handler::handle_register_for_session`]: crate::server::Handler::handle_register_for_session
+
+use crate::secret::CaptchaSecret;
+use hmac::{Mac, NewMac};
+
+type HmacSha256 = hmac::Hmac<sha2::Sha256>;
+
+pub static SESSION_COOKIE: &str = "geetest_session";
+
+/// Bindings kept in the session cookie by [`Server`](crate::server::Server), so that challenges registered in
+/// several tabs of the same session can all be validated. Older bindings are dropped.
+pub const MAX_COOKIE_BINDINGS: usize = 4;
+
+/// Binds challenges issued by register to the session that requested them.
+///
+/// The binding is `HMAC-SHA256(key, session id || challenge)`, so no server-side state is needed:
+/// the binding returned on register must be presented again on validate together with the same session id.
+#[derive(Clone, Debug)]
+pub struct SessionBinder {
+    key: CaptchaSecret,
+}
+
+impl SessionBinder {
+    pub fn new(key: impl Into<CaptchaSecret>) -> Self {
+        Self { key: key.into() }
+    }
+
+    pub fn bind(&self, session_id: &str, challenge: &str) -> String {
+        format!("{:x}", self.mac(session_id, challenge).finalize().into_bytes())
+    }
+
+    pub fn verify(&self, session_id: &str, challenge: &str, binding: &str) -> bool {
+        let binding = match decode_hex(binding) {
+            Some(binding) => binding,
+            None => return false,
+        };
+        self.mac(session_id, challenge).verify(&binding).is_ok()
+    }
+
+    /// Random session id for clients that don't have one yet.
+    pub fn new_session_id() -> String {
+        format!("{:032x}", rand::random::<u128>())
+    }
+
+    fn mac(&self, session_id: &str, challenge: &str) -> HmacSha256 {
+        let mut mac =
+            HmacSha256::new_from_slice(self.key.expose_secret().as_bytes()).expect("HMAC can take key of any size");
+        mac.update(session_id.as_bytes());
+        mac.update(b"\0");
+        mac.update(challenge.as_bytes());
+        mac
+    }
+}
+
+fn decode_hex(value: &str) -> Option<Vec<u8>> {
+    if value.len() % 2 != 0 {
+        return None;
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|i| value.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bind_and_verify() {
+        let binder = SessionBinder::new("session-key");
+        let binding = binder.bind("session-1", "challenge-1");
+        assert_eq!(binding.len(), 64);
+        assert_eq!(binding, binder.bind("session-1", "challenge-1"));
+
+        assert!(binder.verify("session-1", "challenge-1", &binding));
+        assert!(binder.verify("session-1", "challenge-1", &binding.to_uppercase()));
+        assert!(!binder.verify("session-2", "challenge-1", &binding));
+        assert!(!binder.verify("session-1", "challenge-2", &binding));
+        assert!(!SessionBinder::new("other-key").verify("session-1", "challenge-1", &binding));
+    }
+
+    #[test]
+    fn separator_prevents_ambiguity() {
+        let binder = SessionBinder::new("session-key");
+        let binding = binder.bind("ab", "c");
+        assert!(!binder.verify("a", "bc", &binding));
+    }
+
+    #[test]
+    fn malformed_bindings() {
+        let binder = SessionBinder::new("session-key");
+        let binding = binder.bind("session-1", "challenge-1");
+        for malformed in [
+            "",
+            &binding[1..],
+            &binding[..62],
+            &format!("{}zz", &binding[..62]),
+            "é",
+            "aé",
+        ]
+        .iter()
+        {
+            assert!(!binder.verify("session-1", "challenge-1", malformed), "{}", malformed);
+        }
+    }
+
+    #[test]
+    fn new_session_ids_differ() {
+        let session_id = SessionBinder::new_session_id();
+        assert_eq!(session_id.len(), 32);
+        assert_ne!(session_id, SessionBinder::new_session_id());
+    }
+}
@@ -0,0 +1,145 @@
+use crate::error::Error;
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::watch;
+
+type Outcome<T> = Option<Result<T, Arc<Error>>>;
+
+/// Lets concurrent callers share a single in-flight call and its result.
+pub(crate) struct SingleFlight<T> {
+    inflight: Mutex<Option<watch::Receiver<Outcome<T>>>>,
+}
+
+enum Role<T> {
+    Leader(watch::Sender<Outcome<T>>),
+    Follower(watch::Receiver<Outcome<T>>),
+}
+
+/// Clears the in-flight call if the leader is cancelled, so followers retry.
+struct Inflight<'a, T>(&'a SingleFlight<T>);
+
+impl<T> Drop for Inflight<'_, T> {
+    fn drop(&mut self) {
+        *self.0.lock() = None;
+    }
+}
+
+impl<T: Clone> SingleFlight<T> {
+    pub(crate) fn new() -> Self {
+        Self {
+            inflight: Mutex::new(None),
+        }
+    }
+
+    /// Runs `call` unless another one is in flight, in which case its result is returned instead. Errors are always
+    /// returned as [`Error::Shared`], whether or not other callers were waiting.
+    pub(crate) async fn run<F, Fut>(&self, call: F) -> Result<T, Error>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        let mut call = Some(call);
+        loop {
+            let role = {
+                let mut inflight = self.lock();
+                match &*inflight {
+                    Some(receiver) => Role::Follower(receiver.clone()),
+                    None => {
+                        let (sender, receiver) = watch::channel(None);
+                        *inflight = Some(receiver);
+                        Role::Leader(sender)
+                    },
+                }
+            };
+
+            match role {
+                Role::Leader(sender) => {
+                    let guard = Inflight(self);
+                    let result = (call.take().expect("leader runs once"))().await;
+                    drop(guard);
+
+                    let result = result.map_err(Arc::new);
+                    sender.send_replace(Some(result.clone()));
+                    return result.map_err(Error::Shared);
+                },
+                Role::Follower(mut receiver) => loop {
+                    let outcome = receiver.borrow_and_update().clone();
+                    if let Some(result) = outcome {
+                        return result.map_err(Error::Shared);
+                    }
+                    if receiver.changed().await.is_err() {
+                        break;
+                    }
+                },
+            }
+        }
+    }
+}
+
+impl<T> SingleFlight<T> {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<watch::Receiver<Outcome<T>>>> {
+        self.inflight.lock().unwrap_or_else(|error| error.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::future::{join_all, FutureExt};
+    use std::{
+        io,
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    async fn slow_call(calls: &AtomicUsize, result: Result<u32, ()>) -> Result<u32, Error> {
+        calls.fetch_add(1, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        result.map_err(|_| io::Error::new(io::ErrorKind::ConnectionRefused, "unavailable").into())
+    }
+
+    #[tokio::test]
+    async fn followers_share_leader_result() {
+        let flight = SingleFlight::new();
+        let calls = AtomicUsize::new(0);
+
+        let results = join_all((0..4).map(|_| flight.run(|| slow_call(&calls, Ok(7))))).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(results.into_iter().all(|result| result.unwrap() == 7));
+
+        flight.run(|| slow_call(&calls, Ok(8))).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn errors_are_always_shared() {
+        let flight = SingleFlight::new();
+        let calls = AtomicUsize::new(0);
+
+        let lone = flight.run(|| slow_call(&calls, Err(()))).await;
+        assert!(matches!(lone, Err(Error::Shared(error)) if matches!(*error, Error::Io(_))));
+
+        let results = join_all((0..3).map(|_| flight.run(|| slow_call(&calls, Err(()))))).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        for result in results {
+            assert!(matches!(result.unwrap_err().unshared(), Error::Io(_)));
+        }
+    }
+
+    #[tokio::test]
+    async fn follower_retries_after_leader_is_cancelled() {
+        let flight = SingleFlight::new();
+        let calls = AtomicUsize::new(0);
+
+        let mut leader = Box::pin(flight.run(|| slow_call(&calls, Ok(1))));
+        assert!((&mut leader).now_or_never().is_none());
+        let mut follower = Box::pin(flight.run(|| slow_call(&calls, Ok(2))));
+        assert!((&mut follower).now_or_never().is_none());
+        drop(leader);
+
+        assert_eq!(follower.await.unwrap(), 2);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+}
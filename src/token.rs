@@ -0,0 +1,302 @@
+//! Signed "captcha passed" tokens.
+//!
+//! With a [`PassTokenIssuer`], [`Handler`] adds a short-lived `pass_token` to successful validate responses. It
+//! binds the `UserInfo` user id and IP address, scope and expiry, so downstream services can check that a request
+//! passed captcha without calling GeeTest again, as seccodes are single use. [`Server`] doesn't know users, so its
+//! tokens are bound to the client IP only.
+//!
+//! ```
+//! use geetest::{
+//!     token::{PassTokenIssuer, PassTokenVerifier},
+//!     UserInfo,
+//! };
+//! use std::time::Duration;
+//!
+//! let user_info = UserInfo::new().user_id("my-user-id");
+//! let token = PassTokenIssuer::new("token-signing-key", "login", Duration::from_secs(300)).issue(&user_info, false);
+//!
+//! // in another service
+//! let claims = PassTokenVerifier::new("token-signing-key", "login").verify(&token, &user_info);
+//! assert!(claims.is_ok());
+//! ```
+//!
+//! [`Handler`]: crate::server::Handler
+//! [`Server`]: crate::server::Server
+
+use crate::{error::PassTokenError, models::UserInfo, secret::CaptchaSecret};
+use hmac::{Mac, NewMac};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    net::IpAddr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+type HmacSha256 = hmac::Hmac<sha2::Sha256>;
+
+/// Claims of a "captcha passed" token.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PassTokenClaims {
+    #[serde(rename = "sub", default, skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
+    #[serde(rename = "ip", default, skip_serializing_if = "Option::is_none")]
+    pub ip_address: Option<IpAddr>,
+    pub scope: String,
+    /// Token was issued in fail-back mode, i.e. the captcha was not checked by GeeTest.
+    #[serde(default)]
+    pub failback: bool,
+    #[serde(rename = "iat")]
+    pub issued_at: u64,
+    #[serde(rename = "exp")]
+    pub expires_at: u64,
+}
+
+/// Mints short-lived HMAC-SHA256 signed tokens after successful validation.
+///
+/// Token format is `base64url(claims JSON) "." base64url(signature)`, verify it with [`verify_pass_token`].
+#[derive(Clone, Debug)]
+pub struct PassTokenIssuer {
+    key: CaptchaSecret,
+    scope: String,
+    ttl: Duration,
+}
+
+impl PassTokenIssuer {
+    pub fn new(key: impl Into<CaptchaSecret>, scope: impl Into<String>, ttl: Duration) -> Self {
+        Self {
+            key: key.into(),
+            scope: scope.into(),
+            ttl,
+        }
+    }
+
+    pub fn scope(&self) -> &str {
+        &self.scope
+    }
+
+    pub fn issue(&self, user_info: &UserInfo, failback: bool) -> String {
+        let issued_at = now();
+        let claims = PassTokenClaims {
+            user_id: user_info.user_id.clone(),
+            ip_address: user_info.ip_address,
+            scope: self.scope.clone(),
+            failback,
+            issued_at,
+            expires_at: issued_at.saturating_add(self.ttl.as_secs()),
+        };
+
+        let payload = base64::encode_config(
+            serde_json::to_vec(&claims).expect("Claims are always serializable"),
+            base64::URL_SAFE_NO_PAD,
+        );
+        let signature = base64::encode_config(
+            sign(&self.key, &payload).finalize().into_bytes(),
+            base64::URL_SAFE_NO_PAD,
+        );
+        format!("{}.{}", payload, signature)
+    }
+}
+
+/// Checks pass tokens issued by [`PassTokenIssuer`].
+#[derive(Clone, Debug)]
+pub struct PassTokenVerifier {
+    key: CaptchaSecret,
+    scope: String,
+    accept_failback: bool,
+}
+
+impl PassTokenVerifier {
+    pub fn new(key: impl Into<CaptchaSecret>, scope: impl Into<String>) -> Self {
+        Self {
+            key: key.into(),
+            scope: scope.into(),
+            accept_failback: false,
+        }
+    }
+
+    /// Accept tokens issued in fail-back mode, i.e. without the captcha checked by GeeTest. Rejected by default.
+    pub fn accept_failback(mut self, enabled: bool) -> Self {
+        self.accept_failback = enabled;
+        self
+    }
+
+    /// Checks token signature, expiry, scope and fail-back mode, and that the user id and IP address bound to the
+    /// token are the ones in `user_info`.
+    ///
+    /// A token without user id (e.g. issued by [`Server`](crate::server::Server), which doesn't know users) only
+    /// matches `user_info` without user id, the same goes for the IP address.
+    pub fn verify(&self, token: &str, user_info: &UserInfo) -> Result<PassTokenClaims, PassTokenError> {
+        let (payload, signature) = token.split_once('.').ok_or(PassTokenError::Malformed)?;
+        let signature =
+            base64::decode_config(signature, base64::URL_SAFE_NO_PAD).map_err(|_| PassTokenError::Malformed)?;
+        sign(&self.key, payload)
+            .verify(&signature)
+            .map_err(|_| PassTokenError::BadSignature)?;
+
+        let claims: PassTokenClaims = base64::decode_config(payload, base64::URL_SAFE_NO_PAD)
+            .ok()
+            .and_then(|payload| serde_json::from_slice(&payload).ok())
+            .ok_or(PassTokenError::Malformed)?;
+
+        if claims.expires_at <= now() {
+            return Err(PassTokenError::Expired);
+        }
+        if claims.scope != self.scope {
+            return Err(PassTokenError::ScopeMismatch);
+        }
+        if claims.failback && !self.accept_failback {
+            return Err(PassTokenError::Failback);
+        }
+        if claims.user_id != user_info.user_id {
+            return Err(PassTokenError::UserMismatch);
+        }
+        if claims.ip_address != user_info.ip_address {
+            return Err(PassTokenError::IpMismatch);
+        }
+        Ok(claims)
+    }
+}
+
+/// Same as [`PassTokenVerifier::verify`], fail-back tokens are rejected.
+pub fn verify_pass_token(
+    key: &CaptchaSecret,
+    token: &str,
+    scope: &str,
+    user_info: &UserInfo,
+) -> Result<PassTokenClaims, PassTokenError> {
+    PassTokenVerifier::new(key.clone(), scope).verify(token, user_info)
+}
+
+fn sign(key: &CaptchaSecret, payload: &str) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key.expose_secret().as_bytes()).expect("HMAC can take key of any size");
+    mac.update(payload.as_bytes());
+    mac
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "token-signing-key";
+
+    fn user(user_id: Option<&str>, ip_address: Option<&str>) -> UserInfo {
+        UserInfo {
+            user_id: user_id.map(ToOwned::to_owned),
+            client_type: None,
+            ip_address: ip_address.map(|ip| ip.parse().unwrap()),
+        }
+    }
+
+    fn issue(user_info: &UserInfo, failback: bool, ttl: Duration) -> String {
+        PassTokenIssuer::new(KEY, "login", ttl).issue(user_info, failback)
+    }
+
+    fn verify(token: &str, user_info: &UserInfo) -> Result<PassTokenClaims, PassTokenError> {
+        verify_pass_token(&KEY.into(), token, "login", user_info)
+    }
+
+    #[test]
+    fn round_trip() {
+        let alice = user(Some("alice"), Some("203.0.113.7"));
+        let token = issue(&alice, false, Duration::from_secs(60));
+        let claims = verify(&token, &alice).unwrap();
+        assert_eq!(claims.user_id.as_deref(), Some("alice"));
+        assert_eq!(claims.scope, "login");
+        assert_eq!(claims.expires_at - claims.issued_at, 60);
+        assert!(!claims.failback);
+
+        let anonymous = user(None, None);
+        assert!(verify(&issue(&anonymous, false, Duration::from_secs(60)), &anonymous).is_ok());
+    }
+
+    #[test]
+    fn expired() {
+        let alice = user(Some("alice"), None);
+        assert_eq!(
+            verify(&issue(&alice, false, Duration::ZERO), &alice),
+            Err(PassTokenError::Expired)
+        );
+        // Saturates instead of overflowing.
+        assert!(verify(&issue(&alice, false, Duration::MAX), &alice).is_ok());
+    }
+
+    #[test]
+    fn tampered() {
+        let alice = user(Some("alice"), None);
+        let token = issue(&alice, false, Duration::from_secs(60));
+        let (payload, signature) = token.split_once('.').unwrap();
+
+        let claims = String::from_utf8(base64::decode_config(payload, base64::URL_SAFE_NO_PAD).unwrap()).unwrap();
+        let forged = base64::encode_config(claims.replace("alice", "mallory"), base64::URL_SAFE_NO_PAD);
+        let mallory = user(Some("mallory"), None);
+        assert_eq!(
+            verify(&format!("{}.{}", forged, signature), &mallory),
+            Err(PassTokenError::BadSignature)
+        );
+
+        let other_key = PassTokenIssuer::new("other-key", "login", Duration::from_secs(60)).issue(&alice, false);
+        assert_eq!(verify(&other_key, &alice), Err(PassTokenError::BadSignature));
+        assert_eq!(verify(payload, &alice), Err(PassTokenError::Malformed));
+        assert_eq!(
+            verify(&format!("{}.!!", payload), &alice),
+            Err(PassTokenError::Malformed)
+        );
+    }
+
+    #[test]
+    fn scope_mismatch() {
+        let alice = user(Some("alice"), None);
+        let token = issue(&alice, false, Duration::from_secs(60));
+        assert_eq!(
+            verify_pass_token(&KEY.into(), &token, "payment", &alice),
+            Err(PassTokenError::ScopeMismatch)
+        );
+    }
+
+    #[test]
+    fn user_mismatch() {
+        let alice = user(Some("alice"), None);
+        let token = issue(&alice, false, Duration::from_secs(60));
+        assert_eq!(
+            verify(&token, &user(Some("bob"), None)),
+            Err(PassTokenError::UserMismatch)
+        );
+        assert_eq!(verify(&token, &user(None, None)), Err(PassTokenError::UserMismatch));
+
+        // A token without user is not a wildcard.
+        let anonymous = issue(&user(None, None), false, Duration::from_secs(60));
+        assert_eq!(verify(&anonymous, &alice), Err(PassTokenError::UserMismatch));
+    }
+
+    #[test]
+    fn ip_mismatch() {
+        let alice = user(Some("alice"), Some("203.0.113.7"));
+        let token = issue(&alice, false, Duration::from_secs(60));
+        assert_eq!(
+            verify(&token, &user(Some("alice"), Some("203.0.113.8"))),
+            Err(PassTokenError::IpMismatch)
+        );
+        assert_eq!(
+            verify(&token, &user(Some("alice"), None)),
+            Err(PassTokenError::IpMismatch)
+        );
+
+        let no_ip = issue(&user(Some("alice"), None), false, Duration::from_secs(60));
+        assert_eq!(verify(&no_ip, &alice), Err(PassTokenError::IpMismatch));
+    }
+
+    #[test]
+    fn failback_opt_in() {
+        let alice = user(Some("alice"), None);
+        let token = issue(&alice, true, Duration::from_secs(60));
+        assert_eq!(verify(&token, &alice), Err(PassTokenError::Failback));
+
+        let verifier = PassTokenVerifier::new(KEY, "login").accept_failback(true);
+        assert!(verifier.verify(&token, &alice).unwrap().failback);
+    }
+}
@@ -0,0 +1,224 @@
+//! `GeetestConfig` parsing from JSON, TOML and environment variables.
+
+use geetest::{
+    breaker::CircuitBreakerConfig,
+    ratelimit::{RateLimit, RateLimitPolicy, RateLimits},
+    DigestMod, Error, GeetestConfig,
+};
+use std::env;
+
+/// Every test uses its own prefix, environment variables are shared by tests running in parallel.
+fn set_env(prefix: &str, vars: &[(&str, &str)]) {
+    for (name, value) in vars {
+        env::set_var(format!("{}{}", prefix, name), value);
+    }
+}
+
+#[test]
+fn json_minimal() {
+    let config = GeetestConfig::from_json_str(r#"{"captcha_id": "id", "captcha_secret": "secret"}"#).unwrap();
+    assert_eq!(config.captcha_id, "id");
+    assert_eq!(config.captcha_secret.expose_secret(), "secret");
+    assert_eq!(config.digestmod, DigestMod::default());
+    assert_eq!(config.rate_limits, None);
+    assert_eq!(config.circuit_breaker, None);
+    assert!(!config.failback_on_error);
+}
+
+#[test]
+fn json_full() {
+    let config = GeetestConfig::from_json_str(
+        r#"{
+            "captcha_id": "id",
+            "captcha_secret": "secret",
+            "digestmod": "hmac-sha256",
+            "endpoints": {"register": "http://localhost/register.php"},
+            "timeout_ms": 1500,
+            "failback_on_error": true,
+            "trust_forwarded_for": true,
+            "rate_limits": {"validate": {"per_ip": {"burst": 5, "per_second": 0.5}}},
+            "circuit_breaker": {"failure_threshold": 3},
+            "resolve": {"api.geetest.com": ["10.0.0.5"]}
+        }"#,
+    )
+    .unwrap();
+    assert_eq!(config.digestmod, DigestMod::HmacSha256);
+    assert_eq!(config.endpoints.register, "http://localhost/register.php");
+    assert_eq!(config.endpoints.validate, geetest::client::GEETEST_VALIDATE_URL);
+    assert_eq!(config.timeout().unwrap().as_millis(), 1500);
+    assert!(config.failback_on_error && config.trust_forwarded_for);
+    assert_eq!(
+        config.rate_limits.unwrap().validate.per_ip,
+        Some(RateLimit {
+            burst: 5,
+            per_second: 0.5
+        })
+    );
+    assert_eq!(config.circuit_breaker.unwrap().failure_threshold, 3);
+    assert_eq!(
+        config.circuit_breaker.unwrap().cooldown_ms,
+        CircuitBreakerConfig::default().cooldown_ms
+    );
+    let resolved: std::net::IpAddr = "10.0.0.5".parse().unwrap();
+    assert_eq!(config.resolve["api.geetest.com"], vec![resolved]);
+}
+
+#[test]
+fn json_errors() {
+    assert!(matches!(
+        GeetestConfig::from_json_str(r#"{"captcha_id": "id"}"#),
+        Err(Error::Json(_))
+    ));
+    assert!(matches!(
+        GeetestConfig::from_json_str(r#"{"captcha_id": "id", "captcha_secret": "s", "digestmod": "sha1"}"#),
+        Err(Error::Json(_))
+    ));
+}
+
+#[cfg(feature = "config-toml")]
+#[test]
+fn toml() {
+    let config = GeetestConfig::from_toml_str(
+        r#"
+        captcha_id = "id"
+        captcha_secret = "secret"
+        digestmod = "sha256"
+
+        [endpoints]
+        status = "http://localhost/status"
+
+        [rate_limits.register.per_ip]
+        burst = 30
+        per_second = 0.5
+        "#,
+    )
+    .unwrap();
+    assert_eq!(config.digestmod, DigestMod::Sha256);
+    assert_eq!(config.endpoints.status, "http://localhost/status");
+    assert_eq!(
+        config.rate_limits.unwrap().register.per_ip,
+        Some(RateLimit::per_minute(30))
+    );
+
+    assert!(matches!(
+        GeetestConfig::from_toml_str("captcha_id = 1"),
+        Err(Error::Toml(_))
+    ));
+}
+
+#[test]
+fn env_required() {
+    let prefix = "GEETEST_TEST_REQUIRED_";
+    assert!(matches!(
+        GeetestConfig::from_env_prefixed(prefix),
+        Err(Error::Config(_))
+    ));
+
+    set_env(prefix, &[("CAPTCHA_ID", "id"), ("CAPTCHA_SECRET", "secret")]);
+    let config = GeetestConfig::from_env_prefixed(prefix).unwrap();
+    assert_eq!(config.captcha_id, "id");
+    assert_eq!(config.rate_limits, None);
+    assert_eq!(config.circuit_breaker, None);
+}
+
+#[test]
+fn env_options() {
+    let prefix = "GEETEST_TEST_OPTIONS_";
+    set_env(prefix, &[
+        ("CAPTCHA_ID", "id"),
+        ("CAPTCHA_SECRET", "secret"),
+        ("DIGESTMOD", "HMAC_SHA256"),
+        ("STATUS_URL", "http://localhost/status"),
+        ("TIMEOUT_MS", "250"),
+        ("FAILBACK_ON_ERROR", "yes"),
+        ("COALESCE_BYPASS_STATUS", "1"),
+        ("TRUST_FORWARDED_FOR", "true"),
+        ("READY_IN_FAILBACK", "off"),
+        ("RATE_LIMIT_REGISTER_PER_IP", "30/min"),
+        ("RATE_LIMIT_VALIDATE_PER_USER", "2/s"),
+        ("CIRCUIT_BREAKER_COOLDOWN_MS", "1000"),
+        ("PASS_TOKEN_KEY", "token-key"),
+        ("PASS_TOKEN_SCOPE", "login"),
+        ("PASS_TOKEN_TTL_SECS", "300"),
+        ("SESSION_KEY", "session-key"),
+        ("SESSION_SECURE_COOKIE", "true"),
+    ]);
+
+    let config = GeetestConfig::from_env_prefixed(prefix).unwrap();
+    assert_eq!(config.digestmod, DigestMod::HmacSha256);
+    assert_eq!(config.endpoints.status, "http://localhost/status");
+    assert_eq!(config.timeout_ms, Some(250));
+    assert!(config.failback_on_error && config.coalesce_bypass_status && config.trust_forwarded_for);
+    assert!(!config.ready_in_failback);
+    assert_eq!(
+        config.rate_limits,
+        Some(RateLimits {
+            register: RateLimitPolicy {
+                per_ip: Some(RateLimit::per_minute(30)),
+                per_user: None,
+            },
+            validate: RateLimitPolicy {
+                per_ip: None,
+                per_user: Some(RateLimit::per_second(2)),
+            },
+        })
+    );
+    assert_eq!(
+        config.circuit_breaker,
+        Some(CircuitBreakerConfig {
+            cooldown_ms: 1000,
+            ..CircuitBreakerConfig::default()
+        })
+    );
+    #[cfg(feature = "pass-token")]
+    {
+        let pass_token = config.pass_token.as_ref().unwrap();
+        assert_eq!(pass_token.key.expose_secret(), "token-key");
+        assert_eq!((pass_token.scope.as_str(), pass_token.ttl_secs), ("login", 300));
+    }
+    #[cfg(feature = "session-binding")]
+    {
+        let session_binding = config.session_binding.as_ref().unwrap();
+        assert_eq!(session_binding.key.expose_secret(), "session-key");
+        assert!(session_binding.secure_cookie);
+    }
+}
+
+#[test]
+fn env_invalid() {
+    for (name, value) in [
+        ("DIGESTMOD", "sha1"),
+        ("TIMEOUT_MS", "soon"),
+        ("FAILBACK_ON_ERROR", "maybe"),
+        ("RATE_LIMIT_VALIDATE_PER_IP", "10/hour"),
+        ("CIRCUIT_BREAKER_THRESHOLD", "-1"),
+    ]
+    .iter()
+    {
+        let prefix = format!("GEETEST_TEST_INVALID_{}_", name);
+        set_env(&prefix, &[
+            ("CAPTCHA_ID", "id"),
+            ("CAPTCHA_SECRET", "secret"),
+            (name, value),
+        ]);
+        match GeetestConfig::from_env_prefixed(&prefix) {
+            Err(Error::Config(message)) => assert!(message.contains(name), "{}", message),
+            other => panic!(
+                "{}={} accepted: {:?}",
+                name,
+                value,
+                other.map(|config| config.captcha_id)
+            ),
+        }
+    }
+}
+
+#[test]
+fn rate_limit_from_str() {
+    assert_eq!("10/s".parse(), Ok(RateLimit::per_second(10)));
+    assert_eq!(" 30 / min ".parse(), Ok(RateLimit::per_minute(30)));
+    assert_eq!("5/minute".parse(), Ok(RateLimit::per_minute(5)));
+    for invalid in ["", "10", "/s", "x/s", "-1/s", "10/h"].iter() {
+        assert!(invalid.parse::<RateLimit>().is_err(), "{}", invalid);
+    }
+}
@@ -0,0 +1,101 @@
+//! Known-answer tests for built-in challenge digests, computed as official GeeTest SDKs do.
+
+use geetest::{backend::FakeBackend, digest, CaptchaSecret, ChallengeDigest, DigestMod, Error, Handler};
+
+#[cfg(any(feature = "digest-md5", feature = "digest-sha256", feature = "digest-hmac-sha256"))]
+const ORIGIN_CHALLENGE: &str = "8fd3a2d4c1f3f1b9b2a1ac7ea1c3e7d4";
+const SECRET: &str = "geetest-captcha-secret";
+
+#[cfg(any(feature = "digest-md5", feature = "digest-sha256", feature = "digest-hmac-sha256"))]
+fn digest(digestmod: DigestMod, origin_challenge: &str, secret: &str) -> Option<String> {
+    digest::builtin(digestmod).map(|digest| digest.digest(origin_challenge, &CaptchaSecret::from(secret)))
+}
+
+#[cfg(feature = "digest-md5")]
+#[test]
+fn md5_known_answers() {
+    // md5(origin_challenge + secret)
+    assert_eq!(
+        digest(DigestMod::Md5, ORIGIN_CHALLENGE, SECRET).unwrap(),
+        "5ac6f769b619a0f21533507d7be3becf"
+    );
+    assert_eq!(
+        digest(DigestMod::Md5, "", "").unwrap(),
+        "d41d8cd98f00b204e9800998ecf8427e"
+    );
+}
+
+#[cfg(feature = "digest-sha256")]
+#[test]
+fn sha256_known_answers() {
+    // sha256(origin_challenge + secret)
+    assert_eq!(
+        digest(DigestMod::Sha256, ORIGIN_CHALLENGE, SECRET).unwrap(),
+        "778d02af4a4bc0d79eaf2907006197e5d7df61a8c081d7d6548b3214889f818b"
+    );
+    assert_eq!(
+        digest(DigestMod::Sha256, "", "").unwrap(),
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+    );
+}
+
+#[cfg(feature = "digest-hmac-sha256")]
+#[test]
+fn hmac_sha256_known_answers() {
+    // hmac_sha256(key = secret, message = origin_challenge)
+    assert_eq!(
+        digest(DigestMod::HmacSha256, ORIGIN_CHALLENGE, SECRET).unwrap(),
+        "0a8a7fb48d835f6ac859bb55f9affeb26be28dc138adba22a04812b4da1b8470"
+    );
+    assert_eq!(
+        digest(DigestMod::HmacSha256, "", "").unwrap(),
+        "b613679a0814d9ec772f95d778c35fc5ff1697c493715653c6c712144292c5ad"
+    );
+}
+
+#[test]
+fn verify_compares_digests() {
+    let digest = |origin: &str, secret: &CaptchaSecret| format!("{}:{}", origin, secret.expose_secret());
+    let secret = CaptchaSecret::from(SECRET);
+    let expected = format!("origin:{}", SECRET);
+
+    assert!(digest.verify("origin", &secret, &expected));
+    assert!(!digest.verify("origin", &secret, &expected[..expected.len() - 1]));
+    assert!(!digest.verify("origin", &secret, &format!("{}x", expected)));
+    assert!(!digest.verify("other", &secret, &expected));
+    assert!(!digest.verify("origin", &secret, ""));
+}
+
+#[cfg(feature = "digest-hmac-sha256")]
+#[test]
+fn verify_builtin_digest() {
+    let digest = digest::builtin(DigestMod::HmacSha256).unwrap();
+    let secret = CaptchaSecret::from(SECRET);
+    let challenge = digest.digest(ORIGIN_CHALLENGE, &secret);
+
+    assert!(digest.verify(ORIGIN_CHALLENGE, &secret, &challenge));
+    assert!(!digest.verify(ORIGIN_CHALLENGE, &CaptchaSecret::from("other-secret"), &challenge));
+    assert!(!digest.verify(ORIGIN_CHALLENGE, &secret, &challenge.to_uppercase()));
+}
+
+#[test]
+fn build_fails_only_without_digest() {
+    let disabled = [DigestMod::Md5, DigestMod::Sha256, DigestMod::HmacSha256]
+        .iter()
+        .copied()
+        .filter(|digestmod| digest::builtin(*digestmod).is_none());
+    for digestmod in disabled {
+        let backend = FakeBackend::new("captcha-id", digestmod);
+        let error = Handler::builder(backend.clone(), SECRET).build().err();
+        assert!(matches!(error, Some(Error::Config(_))), "{}", digestmod);
+
+        let custom = |origin: &str, _: &CaptchaSecret| origin.to_owned();
+        assert!(Handler::builder(backend, SECRET).digest(custom).build().is_ok());
+    }
+
+    let backend = FakeBackend::new("captcha-id", DigestMod::Md5);
+    assert_eq!(
+        Handler::from_backend(backend, SECRET).is_ok(),
+        digest::builtin(DigestMod::Md5).is_some()
+    );
+}
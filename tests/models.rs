@@ -0,0 +1,451 @@
+use geetest::{
+    models::{
+        ClientRegisterResponse, ClientType, ClientValidateRequest, ClientValidateResponse, DigestMod,
+        ServerRegisterResponse, ServerValidateRequest, ServerValidateResponse, StatusResponse, UserInfo,
+    },
+    protocol::Protocol,
+};
+use rand::{distributions::Alphanumeric, rngs::StdRng, Rng, SeedableRng};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+const ITERATIONS: usize = 2_000;
+
+fn decode<T: DeserializeOwned>(value: Value) -> Result<T, serde_json::Error> {
+    serde_json::from_value(value)
+}
+
+/// Serializing a decoded value and decoding it again gives the same JSON.
+fn assert_stable<T: Serialize + DeserializeOwned>(value: &T) {
+    let encoded = serde_json::to_value(value).unwrap();
+    let decoded: T = decode(encoded.clone()).unwrap();
+    assert_eq!(serde_json::to_value(&decoded).unwrap(), encoded);
+}
+
+fn random_string(rng: &mut StdRng) -> String {
+    let len = rng.gen_range(0..40);
+    match rng.gen_range(0..3) {
+        0 => rng.sample_iter(&Alphanumeric).take(len).map(char::from).collect(),
+        1 => (0..len).map(|_| rng.gen::<char>()).collect(),
+        _ => [
+            "success", "fail", "false", "true", "0", "1", "", " ", "FALSE", "Success",
+        ][rng.gen_range(0..10)]
+        .to_owned(),
+    }
+}
+
+fn random_json(rng: &mut StdRng, depth: u32) -> Value {
+    match rng.gen_range(0..if depth == 0 { 6 } else { 8 }) {
+        0 => Value::Null,
+        1 => Value::Bool(rng.gen()),
+        2 => json!(rng.gen_range(-3i64..4)),
+        3 => json!(rng.gen::<i64>()),
+        4 => json!(rng.gen::<f64>()),
+        5 => Value::String(random_string(rng)),
+        6 => Value::Array((0..rng.gen_range(0..4)).map(|_| random_json(rng, depth - 1)).collect()),
+        _ => Value::Object(
+            (0..rng.gen_range(0..4))
+                .map(|_| (random_string(rng), random_json(rng, depth - 1)))
+                .collect(),
+        ),
+    }
+}
+
+#[test]
+fn status_response_variants() {
+    for (status, expected) in [
+        (json!("success"), true),
+        (json!("fail"), false),
+        (json!(" Success "), true),
+        (json!("FAIL"), false),
+        (json!("true"), true),
+        (json!("false"), false),
+        (json!(true), true),
+        (json!(false), false),
+        (json!(1), true),
+        (json!(0), false),
+    ] {
+        let response: StatusResponse = decode(json!({ "status": status })).unwrap();
+        assert_eq!(response.status, expected, "{}", status);
+    }
+
+    for status in [
+        json!("ok"),
+        json!(""),
+        json!("1"),
+        json!(2),
+        json!(-1),
+        json!(1.0),
+        json!(null),
+        json!([]),
+        json!({}),
+    ] {
+        assert!(
+            decode::<StatusResponse>(json!({ "status": status.clone() })).is_err(),
+            "{}",
+            status
+        );
+    }
+    assert!(decode::<StatusResponse>(json!({})).is_err());
+}
+
+#[test]
+fn status_response_round_trip() {
+    for status in [true, false] {
+        let response = StatusResponse { status };
+        assert_eq!(
+            serde_json::to_value(&response).unwrap(),
+            json!({ "status": if status { "success" } else { "fail" } })
+        );
+        assert_stable(&response);
+    }
+}
+
+#[test]
+fn register_response_success_flag() {
+    let response = |success: Value| json!({ "success": success, "new_captcha": true, "challenge": "c", "gt": "id" });
+    for (success, expected) in [
+        (json!(1), true),
+        (json!(0), false),
+        (json!(true), true),
+        (json!(false), false),
+        (json!("1"), true),
+        (json!("0"), false),
+    ] {
+        let decoded: ClientRegisterResponse = decode(response(success.clone())).unwrap();
+        assert_eq!(decoded.success, expected, "{}", success);
+    }
+
+    for success in [
+        json!(2),
+        json!(255),
+        json!(-1),
+        json!("yes"),
+        json!(""),
+        json!(null),
+        json!(0.5),
+    ] {
+        assert!(
+            decode::<ClientRegisterResponse>(response(success.clone())).is_err(),
+            "{}",
+            success
+        );
+    }
+}
+
+#[test]
+fn server_register_response_failback_challenge() {
+    for (challenge, expected) in [
+        (
+            "8fd3a2d4c1f3f1b9b2a1ac7ea1c3e7d4",
+            Some("8fd3a2d4c1f3f1b9b2a1ac7ea1c3e7d4"),
+        ),
+        ("", None),
+        (" ", None),
+        ("0", None),
+    ] {
+        let body = json!({ "challenge": challenge }).to_string();
+        let decoded: ServerRegisterResponse = serde_json::from_str(&body).unwrap();
+        assert_eq!(decoded.origin_challenge(), expected, "{:?}", challenge);
+        assert_eq!(
+            Protocol::register_response(body.as_bytes()).unwrap().as_deref(),
+            expected,
+            "{:?}",
+            challenge
+        );
+    }
+    assert!(Protocol::register_response(br#"{"challenge": 0}"#).is_err());
+    assert!(Protocol::register_response(b"{}").is_err());
+}
+
+#[test]
+fn server_validate_response_seccode() {
+    for (seccode, expected) in [
+        (json!("false"), None),
+        (json!("FALSE"), None),
+        (json!(false), None),
+        (json!(null), None),
+        (json!("a1b2c3"), Some("a1b2c3")),
+    ] {
+        let decoded: ServerValidateResponse = decode(json!({ "seccode": seccode })).unwrap();
+        assert_eq!(decoded.seccode.as_deref(), expected, "{}", seccode);
+    }
+
+    for seccode in [
+        json!(""),
+        json!("  "),
+        json!(true),
+        json!(0),
+        json!(1),
+        json!([]),
+        json!({}),
+    ] {
+        assert!(
+            decode::<ServerValidateResponse>(json!({ "seccode": seccode.clone() })).is_err(),
+            "{}",
+            seccode
+        );
+    }
+    assert!(decode::<ServerValidateResponse>(json!({})).is_err());
+}
+
+#[test]
+fn unknown_fields_are_ignored() {
+    let extra = json!({ "extra": [1, 2, 3], "data": { "nested": null } });
+    let with_extra = |mut value: Value| {
+        value
+            .as_object_mut()
+            .unwrap()
+            .extend(extra.as_object().unwrap().clone());
+        value
+    };
+
+    assert!(decode::<StatusResponse>(with_extra(json!({ "status": "success" }))).is_ok());
+    assert!(decode::<ServerValidateResponse>(with_extra(json!({ "seccode": "s" }))).is_ok());
+    assert!(decode::<ClientRegisterResponse>(with_extra(
+        json!({ "success": 1, "new_captcha": true, "challenge": "c", "gt": "id" })
+    ))
+    .is_ok());
+    assert!(
+        decode::<ClientValidateResponse>(with_extra(json!({ "result": "success", "version": "v", "msg": null })))
+            .is_ok()
+    );
+}
+
+#[test]
+fn round_trips_random_values() {
+    let mut rng = StdRng::seed_from_u64(47);
+    for _ in 0..ITERATIONS {
+        assert_stable(&StatusResponse { status: rng.gen() });
+
+        let seccode = Some(random_string(&mut rng))
+            .filter(|seccode| !seccode.trim().is_empty() && !seccode.trim().eq_ignore_ascii_case("false"));
+        let response = ServerValidateResponse {
+            seccode: seccode.clone(),
+        };
+        assert_stable(&response);
+        let decoded: ServerValidateResponse = decode(serde_json::to_value(&response).unwrap()).unwrap();
+        assert_eq!(decoded.seccode, seccode);
+
+        let response = ClientRegisterResponse {
+            success: rng.gen(),
+            new_captcha: rng.gen(),
+            challenge: random_string(&mut rng),
+            captcha_id: random_string(&mut rng),
+            session_binding: rng.gen::<bool>().then(|| random_string(&mut rng)),
+        };
+        assert_stable(&response);
+
+        let mut response = ClientValidateResponse::error(random_string(&mut rng));
+        response.result = rng.gen();
+        assert_stable(&response);
+    }
+}
+
+#[test]
+fn user_info_ip_address_round_trip() {
+    let mut rng = StdRng::seed_from_u64(4747);
+    for _ in 0..ITERATIONS {
+        let ip_address: IpAddr = if rng.gen() {
+            Ipv4Addr::from(rng.gen::<u32>()).into()
+        } else {
+            Ipv6Addr::from(rng.gen::<u128>()).into()
+        };
+        let user_info = UserInfo::new().ip_address(ip_address);
+
+        let decoded: UserInfo = decode(serde_json::to_value(&user_info).unwrap()).unwrap();
+        assert_eq!(decoded.ip_address, Some(ip_address));
+        let decoded: UserInfo = serde_qs::from_str(&serde_qs::to_string(&user_info).unwrap()).unwrap();
+        assert_eq!(decoded.ip_address, Some(ip_address));
+    }
+
+    assert!(decode::<UserInfo>(json!({ "ip_address": "not an ip" })).is_err());
+    assert!(decode::<UserInfo>(json!({ "ip_address": 127 })).is_err());
+    assert_eq!(decode::<UserInfo>(json!({})).unwrap().ip_address, None);
+}
+
+/// Arbitrary JSON never panics a decoder, and whatever is accepted re-encodes to a stable value.
+#[test]
+fn debug_redacts_ip_address_and_seccode() {
+    let user_info = UserInfo::new()
+        .user_id("my-user-id")
+        .ip_address("203.0.113.7".parse().unwrap());
+    let debug = format!("{:?}", user_info);
+    assert!(debug.contains("my-user-id"), "{}", debug);
+    assert!(!debug.contains("203.0.113.7"), "{}", debug);
+    assert!(!format!("{:#?}", user_info).contains("203.0.113.7"));
+    let ipv6 = UserInfo::new().ip_address("2001:db8::1".parse().unwrap());
+    assert!(!format!("{:?}", ipv6).contains("2001:db8"));
+
+    let request = ServerValidateRequest {
+        user_info,
+        json_format: 1,
+        sdk: "sdk".to_owned(),
+        captcha_id: "captcha-id".to_owned(),
+        seccode: "secret-seccode".to_owned(),
+        challenge: "my-challenge".to_owned(),
+    };
+    let debug = format!("{:?}", request);
+    assert!(
+        debug.contains("my-challenge") && debug.contains("captcha-id"),
+        "{}",
+        debug
+    );
+    assert!(
+        !debug.contains("secret-seccode") && !debug.contains("203.0.113.7"),
+        "{}",
+        debug
+    );
+
+    let request = ClientValidateRequest {
+        challenge: "my-challenge".to_owned(),
+        validate: "secret-validate".to_owned(),
+        seccode: "secret-seccode".to_owned(),
+        session_binding: None,
+    };
+    let debug = format!("{:?}", request);
+    assert!(debug.contains("my-challenge"), "{}", debug);
+    assert!(!debug.contains("secret-"), "{}", debug);
+}
+
+#[test]
+fn decoders_accept_or_reject_random_json() {
+    fn check<T: Serialize + DeserializeOwned>(value: Value) {
+        if let Ok(decoded) = decode::<T>(value) {
+            assert_stable(&decoded);
+        }
+    }
+
+    let mut rng = StdRng::seed_from_u64(0x6765_6574);
+    for _ in 0..ITERATIONS {
+        let field = random_json(&mut rng, 2);
+        check::<StatusResponse>(json!({ "status": field.clone() }));
+        check::<ServerValidateResponse>(json!({ "seccode": field.clone() }));
+        check::<ClientValidateResponse>(json!({ "result": field.clone(), "version": "v", "msg": null }));
+        check::<ClientRegisterResponse>(
+            json!({ "success": field.clone(), "new_captcha": true, "challenge": "c", "gt": "id" }),
+        );
+        check::<UserInfo>(json!({ "ip_address": field }));
+
+        let document = random_json(&mut rng, 3);
+        check::<StatusResponse>(document.clone());
+        check::<ServerValidateResponse>(document.clone());
+        check::<ClientValidateResponse>(document.clone());
+        check::<ClientRegisterResponse>(document.clone());
+        check::<UserInfo>(document);
+    }
+}
+
+#[test]
+fn digestmod_from_str_display() {
+    for digestmod in [DigestMod::Md5, DigestMod::Sha256, DigestMod::HmacSha256]
+        .iter()
+        .copied()
+    {
+        assert_eq!(digestmod.to_string().parse(), Ok(digestmod));
+        assert_eq!(digestmod.to_string().to_uppercase().parse(), Ok(digestmod));
+        assert_eq!(decode::<DigestMod>(json!(digestmod.as_str())).unwrap(), digestmod);
+    }
+    assert_eq!("hmac_sha256".parse(), Ok(DigestMod::HmacSha256));
+    assert!("sha1".parse::<DigestMod>().is_err());
+    assert!("".parse::<DigestMod>().is_err());
+}
+
+#[test]
+fn client_type_from_str_display() {
+    for client_type in [
+        ClientType::Web,
+        ClientType::Mobile,
+        ClientType::Native,
+        ClientType::Unknown,
+    ]
+    .iter()
+    .copied()
+    {
+        assert_eq!(client_type.to_string().parse(), Ok(client_type));
+        assert_eq!(
+            format!(" {} ", client_type.to_string().to_uppercase()).parse(),
+            Ok(client_type)
+        );
+        assert_eq!(decode::<ClientType>(json!(client_type.as_str())).unwrap(), client_type);
+    }
+    assert_eq!("mobile".parse(), Ok(ClientType::Mobile));
+    assert!("desktop".parse::<ClientType>().is_err());
+}
+
+#[test]
+fn client_type_from_user_agent() {
+    let cases = [
+        // Desktop browsers.
+        (
+            "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 \
+             Safari/537.36",
+            ClientType::Web,
+        ),
+        (
+            "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 \
+             Safari/537.36 Edg/120.0.2210.91",
+            ClientType::Web,
+        ),
+        (
+            "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/605.1.15 (KHTML, like Gecko) \
+             Version/17.2 Safari/605.1.15",
+            ClientType::Web,
+        ),
+        (
+            "Mozilla/5.0 (X11; Ubuntu; Linux x86_64; rv:121.0) Gecko/20100101 Firefox/121.0",
+            ClientType::Web,
+        ),
+        (
+            "Opera/9.80 (Windows NT 6.1; WOW64) Presto/2.12.388 Version/12.18",
+            ClientType::Web,
+        ),
+        // Mobile browsers and in-app web views.
+        (
+            "Mozilla/5.0 (Linux; Android 14; Pixel 8) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.6099.144 \
+             Mobile Safari/537.36",
+            ClientType::Mobile,
+        ),
+        (
+            "Mozilla/5.0 (iPhone; CPU iPhone OS 17_2 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) \
+             Version/17.2 Mobile/15E148 Safari/604.1",
+            ClientType::Mobile,
+        ),
+        (
+            "Mozilla/5.0 (iPad; CPU OS 17_2 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.2 \
+             Safari/604.1",
+            ClientType::Mobile,
+        ),
+        (
+            "Mozilla/5.0 (Linux; Android 13; SAMSUNG SM-S918B) AppleWebKit/537.36 (KHTML, like Gecko) \
+             SamsungBrowser/23.0 Chrome/115.0.0.0 Mobile Safari/537.36",
+            ClientType::Mobile,
+        ),
+        (
+            "Mozilla/5.0 (iPhone; CPU iPhone OS 16_6 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) \
+             Mobile/15E148 MicroMessenger/8.0.43(0x18002b2c) NetType/WIFI Language/zh_CN",
+            ClientType::Mobile,
+        ),
+        (
+            "Opera/9.80 (J2ME/MIDP; Opera Mini/9.80 (S60; SymbOS; Opera Mobi/23.348; U; en) Presto/2.5.25 \
+             Version/10.54",
+            ClientType::Mobile,
+        ),
+        // HTTP libraries of native apps.
+        ("okhttp/4.12.0", ClientType::Native),
+        ("MyApp/3.1.0 CFNetwork/1410.0.3 Darwin/22.6.0", ClientType::Native),
+        (
+            "Dalvik/2.1.0 (Linux; U; Android 13; Pixel 7 Build/TQ3A.230901.001)",
+            ClientType::Native,
+        ),
+        ("Alamofire/5.8.1", ClientType::Native),
+        // Anything else.
+        ("curl/8.4.0", ClientType::Unknown),
+        ("python-requests/2.31.0", ClientType::Unknown),
+        ("", ClientType::Unknown),
+    ];
+    for (user_agent, expected) in cases.iter() {
+        assert_eq!(ClientType::from_user_agent(user_agent), *expected, "{}", user_agent);
+    }
+}
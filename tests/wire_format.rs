@@ -0,0 +1,265 @@
+//! Requests sent to GeeTest compared with golden files, and with the parameters official Java, Python and Node
+//! SDKs send.
+//!
+//! Run with `GEETEST_BLESS=1` to rewrite the golden files after an intended change.
+
+use geetest::{client::Endpoints, Client, ClientType, DigestMod, UserInfo};
+use hyper::{
+    body,
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, Server,
+};
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    env, fs,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+const CAPTCHA_ID: &str = "647f5ed2ed8acb4be36784e01556bb71";
+const CHALLENGE: &str = "8fd3a2d4c1f3f1b9b2a1ac7ea1c3e7d4";
+const SECCODE: &str = "b5b7e0c1d2a4f6e8|jordan";
+
+/// Parameters official SDKs send to `register.php`, `gt` is the captcha id.
+const REGISTER_PARAMS: &[&str] = &[
+    "gt",
+    "digestmod",
+    "json_format",
+    "sdk",
+    "user_id",
+    "client_type",
+    "ip_address",
+];
+/// Parameters official SDKs send to `validate.php`, `captchaid` is the captcha id.
+const VALIDATE_PARAMS: &[&str] = &[
+    "captchaid",
+    "challenge",
+    "seccode",
+    "json_format",
+    "sdk",
+    "user_id",
+    "client_type",
+    "ip_address",
+];
+
+type Captured = Arc<Mutex<Vec<String>>>;
+
+/// Records requests as `METHOD /path?query`, followed by content type and body lines for requests with a body.
+async fn stub_server(captured: Captured) -> SocketAddr {
+    let make_service = make_service_fn(move |_| {
+        let captured = captured.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                let captured = captured.clone();
+                async move {
+                    let (parts, body) = request.into_parts();
+                    let body = body::to_bytes(body).await.unwrap();
+                    let mut record = format!("{} {}", parts.method, parts.uri);
+                    if !body.is_empty() {
+                        let content_type = parts.headers[hyper::header::CONTENT_TYPE].to_str().unwrap();
+                        record += &format!(
+                            "\n  content-type: {}\n  {}",
+                            content_type,
+                            String::from_utf8_lossy(&body)
+                        );
+                    }
+                    captured.lock().unwrap().push(record);
+
+                    let reply = match parts.uri.path() {
+                        "/register.php" => format!(r#"{{"challenge":"{}"}}"#, CHALLENGE),
+                        _ => r#"{"seccode":"false"}"#.to_owned(),
+                    };
+                    Ok::<_, Infallible>(Response::new(Body::from(reply)))
+                }
+            }))
+        }
+    });
+
+    let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+    let addr = server.local_addr();
+    tokio::spawn(server);
+    addr
+}
+
+/// Every combination of `UserInfo` fields, plus a user id needing escaping.
+fn user_infos() -> Vec<(String, UserInfo)> {
+    let user_ids = [None, Some("user-42"), Some("a b+c&d=e[0]/é")];
+    let client_types = [
+        None,
+        Some(ClientType::Web),
+        Some(ClientType::Mobile),
+        Some(ClientType::Native),
+        Some(ClientType::Unknown),
+    ];
+    let ip_addresses: [Option<IpAddr>; 3] = [
+        None,
+        Some("203.0.113.7".parse().unwrap()),
+        Some("2001:db8::1".parse().unwrap()),
+    ];
+
+    let mut user_infos = Vec::new();
+    for user_id in user_ids {
+        for client_type in client_types {
+            for ip_address in ip_addresses {
+                let label = format!(
+                    "user_id={} client_type={} ip_address={}",
+                    user_id.unwrap_or("-"),
+                    client_type.map_or("-", |client_type| client_type.as_str()),
+                    ip_address.map_or("-".to_owned(), |ip_address| ip_address.to_string()),
+                );
+                let user_info = UserInfo {
+                    user_id: user_id.map(ToOwned::to_owned),
+                    client_type,
+                    ip_address,
+                };
+                user_infos.push((label, user_info));
+            }
+        }
+    }
+    user_infos
+}
+
+fn decode_form(form: &str) -> HashMap<String, String> {
+    form.split('&')
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (decode_component(key), decode_component(value))
+        })
+        .collect()
+}
+
+fn decode_component(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' => {
+                decoded.push(u8::from_str_radix(&value[i + 1..i + 3], 16).unwrap());
+                i += 2;
+            },
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8(decoded).unwrap()
+}
+
+fn assert_sdk_params(params: &HashMap<String, String>, allowed: &[&str], user_info: &UserInfo) {
+    for key in params.keys() {
+        assert!(allowed.contains(&key.as_str()), "unexpected parameter {}", key);
+    }
+    assert_eq!(params.get("json_format").map(String::as_str), Some("1"));
+    assert!(params.contains_key("sdk"));
+    assert_eq!(params.get("user_id"), user_info.user_id.as_ref());
+    assert_eq!(
+        params.get("client_type").map(String::as_str),
+        user_info.client_type.map(|client_type| client_type.as_str())
+    );
+    assert_eq!(
+        params.get("ip_address").cloned(),
+        user_info.ip_address.map(|ip_address| ip_address.to_string())
+    );
+}
+
+fn check_golden(name: &str, actual: &str) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(name);
+    if env::var_os("GEETEST_BLESS").is_some() {
+        fs::write(&path, actual).unwrap();
+        return;
+    }
+
+    let expected = fs::read_to_string(&path).unwrap_or_default();
+    for (line, (expected, actual)) in expected.lines().zip(actual.lines()).enumerate() {
+        assert_eq!(expected, actual, "{}:{} differs", name, line + 1);
+    }
+    assert_eq!(
+        expected.lines().count(),
+        actual.lines().count(),
+        "{} line count differs, run with GEETEST_BLESS=1 to update",
+        name
+    );
+}
+
+#[tokio::test]
+async fn requests_match_golden_files() {
+    let captured = Captured::default();
+    let addr = stub_server(captured.clone()).await;
+    let endpoints = Endpoints {
+        register: format!("http://{}/register.php", addr),
+        validate: format!("http://{}/validate.php", addr),
+        status: format!("http://{}/v1/bypass_status.php", addr),
+    };
+
+    let mut register = String::new();
+    let mut validate = String::new();
+    // Validate requests don't depend on digestmod, they are recorded for the first one only.
+    let mut validate_requests = Vec::new();
+    for digestmod in [DigestMod::Md5, DigestMod::Sha256, DigestMod::HmacSha256] {
+        let client = Client::builder(CAPTCHA_ID)
+            .digestmod(digestmod)
+            .endpoints(endpoints.clone())
+            .build();
+
+        for (index, (label, user_info)) in user_infos().into_iter().enumerate() {
+            assert_eq!(
+                client.register(user_info.clone()).await.unwrap().as_deref(),
+                Some(CHALLENGE)
+            );
+            let request = captured.lock().unwrap().pop().unwrap();
+            register += &format!("# digestmod={} {}\n{}\n", digestmod, label, request);
+
+            let query = request.split_once('?').unwrap().1;
+            let params = decode_form(query);
+            assert_sdk_params(&params, REGISTER_PARAMS, &user_info);
+            assert_eq!(params["gt"], CAPTCHA_ID);
+            assert_eq!(params["digestmod"], digestmod.as_str());
+
+            let seccode = client
+                .validate(SECCODE.to_owned(), CHALLENGE.to_owned(), user_info.clone())
+                .await
+                .unwrap();
+            assert_eq!(seccode, None);
+            let request = captured.lock().unwrap().pop().unwrap();
+            match validate_requests.get(index) {
+                Some(first) => assert_eq!(&request, first, "digestmod={} {}", digestmod, label),
+                None => {
+                    validate += &format!("# {}\n{}\n", label, request);
+                    validate_requests.push(request.clone());
+                },
+            }
+
+            assert!(request.contains("content-type: application/x-www-form-urlencoded"));
+            let form = request.lines().last().unwrap().trim();
+            let params = decode_form(form);
+            assert_sdk_params(&params, VALIDATE_PARAMS, &user_info);
+            assert_eq!(params["captchaid"], CAPTCHA_ID);
+            assert_eq!(params["challenge"], CHALLENGE);
+            assert_eq!(params["seccode"], SECCODE);
+        }
+    }
+
+    check_golden("register.txt", &register);
+    check_golden("validate.txt", &validate);
+}
+
+#[tokio::test]
+async fn bypass_status_request() {
+    let captured = Captured::default();
+    let addr = stub_server(captured.clone()).await;
+    let client = Client::builder(CAPTCHA_ID)
+        .endpoints(Endpoints {
+            status: format!("http://{}/v1/bypass_status.php", addr),
+            ..Endpoints::default()
+        })
+        .build();
+
+    let _ = client.bypass_status().await;
+    let request = captured.lock().unwrap().pop().unwrap();
+    assert_eq!(request, format!("GET /v1/bypass_status.php?gt={}", CAPTCHA_ID));
+}